@@ -1,30 +1,137 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// byte offsets into the source file, as handed out by the parser's @L/@R
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    pub lo: usize,
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(lo: usize, hi: usize) -> Self {
+        Span { lo, hi }
+    }
+}
+
+// turns byte offsets back into 1-based line/column pairs for debug info and diagnostics
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(src: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, c) in src.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex { line_starts }
+    }
+
+    pub fn line_col(&self, offset: usize) -> (u32, u32) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        ((line + 1) as u32, (offset - self.line_starts[line] + 1) as u32)
+    }
+}
+
+// an error or warning that points at a place in the source. errors are passed around inside
+// anyhow errors, warnings are collected in a Vec. main prints the span as file:line:col
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub msg: String,
+    // name of the lint a warning belongs to, for -W and -A
+    pub lint: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(span: Span, msg: S) -> Self {
+        Diagnostic {
+            span,
+            msg: msg.into(),
+            lint: None,
+        }
+    }
+
+    pub fn warning<S: Into<String>>(lint: &'static str, span: Span, msg: S) -> Self {
+        Diagnostic {
+            span,
+            msg: msg.into(),
+            lint: Some(lint),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "prog")]
 pub struct Prog {
+    pub imports: Vec<Import>,
     pub externs: Vec<Extern>,
+    pub globals: Vec<Global>,
     pub funcs: Vec<Func>,
 }
 
+// import "path.ek"; the path is relative to the importing file
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Import {
+    pub path: String,
+    #[serde(skip)]
+    pub span: Span,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Extern {
     pub type_: Type,
     pub globid: String,
     pub args: Option<Vec<Type>>,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+// global int $x = 0; or const float $PI = 3.14159; a variable every function in the file can
+// use, whose initial value has to be known at compile time
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Global {
+    // const, so nothing can assign to it or take a ref to it
+    pub constant: bool,
+    pub vdecl: VDecl,
+    pub exp: Exp,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Func {
+    // pub def, callable from files that import this one
+    pub public: bool,
+    // the name of a `test "name" { ... }` item, which is a void function with no arguments
+    pub test: Option<String>,
     pub type_: Type,
     pub globid: String,
     pub args: Option<Vec<VDecl>>,
     pub blk: Block,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Block {
     pub stmts: Option<Vec<Box<Stmt>>>,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 /*
@@ -32,7 +139,23 @@ pub struct Block {
  * using the output of the parser generated by pest from kaleidoscope.pest
  */
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, lo: usize, hi: usize) -> Box<Self> {
+        Box::new(Stmt {
+            kind,
+            span: Span::new(lo, hi),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum StmtKind {
     Blk(Block),
     ReturnStmt(Option<Exp>),
     VDeclStmt {
@@ -51,10 +174,34 @@ pub enum Stmt {
     },
     PrintStmt(Exp),
     PrintStmtSlit(String),
+    // stops the program (or fails the test) when the exp is false
+    Assert(Exp),
+    AssertEq {
+        lhs: Exp,
+        rhs: Exp,
+    },
+    // where the parser recovered from a syntax error
+    Error,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
-pub enum Exp {
+pub struct Exp {
+    pub kind: ExpKind,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl Exp {
+    pub fn new(kind: ExpKind, lo: usize, hi: usize) -> Box<Self> {
+        Box::new(Exp {
+            kind,
+            span: Span::new(lo, hi),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ExpKind {
     Assign {
         varid: String,
         exp: Box<Exp>,
@@ -78,14 +225,36 @@ pub enum Exp {
         globid: String,
         exps: Option<Vec<Box<Exp>>>,
     },
+    // new int[$n], an array of $n zeroes
+    NewArray {
+        type_: Type,
+        len: Box<Exp>,
+    },
+    // len($a)
+    Len(Box<Exp>),
+    // $a[$i]
+    Index {
+        varid: String,
+        index: Box<Exp>,
+    },
+    // $a[$i] = exp
+    AssignIndex {
+        varid: String,
+        index: Box<Exp>,
+        exp: Box<Exp>,
+    },
+    // a literal the parser couldn't read
+    Error,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VDecl {
     pub type_: Type,
     pub varid: String,
+    #[serde(skip)]
+    pub span: Span,
 }
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Lit {
     LitBool(bool),
     LitInt(i32),
@@ -100,6 +269,8 @@ pub enum Type {
     BoolType,
     VoidType,
     Ref(bool, Box<Type>), // noalias, type
+    // int[] and so on, a reference counted array on the heap
+    Array(Box<Type>),
 }
 
 //binary operators
@@ -0,0 +1,136 @@
+use crate::dylib::SharedLib;
+use crate::host::HostFns;
+use crate::jit::{self, CompileOptions};
+use crate::modules::Module;
+use crate::optimize::{parse_pass_list, OptLevel, Pipeline};
+use crate::typecheck::TCProg;
+use anyhow::Result;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+// the optimization groups compared in results/*.csv and in the report. "all" is every group
+// in order, same as it was in run_tests.py
+static PASS_GROUPS: &[(&str, &str)] = &[
+    ("none", ""),
+    ("functions", "argpromotion,basicaa,inline"),
+    ("control", "simplifycfg,adce,strip-dead-prototypes,basicaa"),
+    ("memory", "instcombine,mem2reg,basicaa"),
+    (
+        "other",
+        "indvars,loop-vectorize,reassociate,sccp,deadargelim,basicaa",
+    ),
+];
+
+pub struct BenchOptions {
+    pub warmup: u32,
+    pub iterations: u32,
+    // where the csv goes, results/test_<name>-aa.csv if not given
+    pub csv_path: Option<String>,
+    // from --link-lib and -l, for the program's externs
+    pub libs: Vec<SharedLib>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    pub median: f64,
+    pub min: u128,
+    pub stddev: f64,
+}
+
+// median like python's statistics.median, stddev is the sample standard deviation
+pub fn summarize(samples: &[u128]) -> Summary {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let n = sorted.len();
+    let median = if n % 2 == 1 {
+        sorted[n / 2] as f64
+    } else {
+        (sorted[n / 2 - 1] + sorted[n / 2]) as f64 / 2.0
+    };
+    let mean = sorted.iter().sum::<u128>() as f64 / n as f64;
+    let stddev = if n > 1 {
+        let sq_diffs: f64 = sorted.iter().map(|s| (*s as f64 - mean).powi(2)).sum();
+        (sq_diffs / (n - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+    Summary {
+        median,
+        min: sorted[0],
+        stddev,
+    }
+}
+
+// named the way the files in results/ are
+pub fn default_csv_path(input_filename: &str) -> String {
+    let name = Path::new(input_filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(input_filename);
+    format!("results/test_{}-aa.csv", name)
+}
+
+// compile and run the program with each pass group, print a summary and write the medians
+// out as csv
+pub fn bench(
+    modules: Vec<Module<TCProg>>,
+    host: &HostFns,
+    bench_options: BenchOptions,
+) -> Result<()> {
+    let mut groups = vec![];
+    let mut all = vec![];
+    for (name, passes) in PASS_GROUPS.iter() {
+        let passes = parse_pass_list(passes)?;
+        all.extend(passes.iter().cloned());
+        groups.push((*name, passes));
+    }
+    groups.push(("all", all));
+
+    let mut csv = String::from("optimizations,pipeline time (ns),run time (ns)\n");
+    println!(
+        "{:<10} {:>14} {:>14} {:>14} {:>14} {:>14} {:>14}",
+        "group",
+        "compile med",
+        "compile min",
+        "compile sd",
+        "run med",
+        "run min",
+        "run sd"
+    );
+    let mut options = CompileOptions {
+        opt_level: OptLevel::O0,
+        pipeline: Pipeline::default(),
+        time: false,
+        time_passes: None,
+        debug: false,
+        libs: bench_options.libs,
+        entry: None,
+    };
+    for (name, passes) in groups {
+        options.pipeline.passes = passes;
+        let samples = jit::bench(
+            &modules,
+            host,
+            &options,
+            bench_options.warmup,
+            bench_options.iterations,
+        )?;
+        let compile = summarize(&samples.compile);
+        let run = summarize(&samples.run);
+        println!(
+            "{:<10} {:>14.1} {:>14} {:>14.1} {:>14.1} {:>14} {:>14.1}",
+            name, compile.median, compile.min, compile.stddev, run.median, run.min, run.stddev
+        );
+        csv.push_str(&format!("{},{:.1},{:.1}\n", name, compile.median, run.median));
+    }
+
+    let csv_path = match &bench_options.csv_path {
+        Some(path) => path.clone(),
+        None => default_csv_path(&modules.last().unwrap().filename),
+    };
+    if let Some(dir) = Path::new(&csv_path).parent() {
+        create_dir_all(dir)?;
+    }
+    write(&csv_path, csv)?;
+    Ok(())
+}
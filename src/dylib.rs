@@ -0,0 +1,57 @@
+use anyhow::{anyhow, Result};
+use libc::{c_void, dlerror, dlopen, dlsym, RTLD_DEFAULT, RTLD_GLOBAL, RTLD_NOW};
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+use std::ffi::{CStr, CString};
+use std::fs::read_to_string;
+
+// a shared library loaded with --link-lib or -l. it stays loaded until ekcc exits, since
+// jitted code can call into it any time
+pub struct SharedLib {
+    handle: *mut c_void,
+}
+
+// -l m means libm.so (libm.dylib on macos), like cc
+pub fn lib_filename(name: &str) -> String {
+    format!("{}{}{}", DLL_PREFIX, name, DLL_SUFFIX)
+}
+
+pub fn open(filename: &str) -> Result<SharedLib> {
+    let c_filename = CString::new(filename)?;
+    let handle = unsafe { dlopen(c_filename.as_ptr(), RTLD_NOW | RTLD_GLOBAL) };
+    if handle.is_null() {
+        let msg = unsafe { CStr::from_ptr(dlerror()) }
+            .to_string_lossy()
+            .to_string();
+        // glibc ships libm.so and friends as linker scripts naming the real library, which
+        // dlopen can't read. glibc's message starts with the path it found
+        if let Some(script) = msg.strip_suffix(": invalid ELF header") {
+            if let Some(target) = linker_script_target(script) {
+                return open(&target);
+            }
+        }
+        return Err(anyhow!("could not load {}: {}", filename, msg));
+    }
+    Ok(SharedLib { handle })
+}
+
+// the first shared library in a GNU ld script's GROUP or INPUT
+fn linker_script_target(path: &str) -> Option<String> {
+    let script = read_to_string(path).ok()?;
+    let start = script.find("GROUP").or_else(|| script.find("INPUT"))?;
+    script[start..]
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .find(|word| word.contains(".so"))
+        .map(|word| word.to_string())
+}
+
+// the address of symbol in the first library that has it, or else anywhere in ekcc itself
+// (libc, for one)
+pub fn lookup(libs: &[SharedLib], symbol: &str) -> Option<usize> {
+    let c_symbol = CString::new(symbol).ok()?;
+    libs.iter()
+        .map(|lib| lib.handle)
+        .chain(std::iter::once(RTLD_DEFAULT))
+        .map(|handle| unsafe { dlsym(handle, c_symbol.as_ptr()) })
+        .find(|addr| !addr.is_null())
+        .map(|addr| addr as usize)
+}
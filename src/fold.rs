@@ -0,0 +1,358 @@
+use crate::ast::{BOp, Diagnostic, Lit, Span, UOp};
+use crate::typecheck::{
+    falls_through, maybe_deref, TCAtomType, TCBlock, TCExp, TCFunc, TCProg, TCStmt, TCType,
+    TypedExp,
+};
+use anyhow::Result;
+
+// optimizations on the typed AST, done before anything is handed to llvm:
+// constant folding of binops, unary ops and casts, dropping statements that can't be reached, and
+// getting rid of if (true)/if (false)/while (false).
+// folding matches what jit.rs would have computed at run time, so anything llvm leaves
+// undefined (int division by zero, out of range float to int casts) is left alone, and cint
+// arithmetic that would have failed its overflow check at run time is an error here.
+pub fn fold_prog(prog: TCProg) -> Result<TCProg> {
+    Ok(TCProg {
+        externs: prog.externs,
+        globals: prog.globals,
+        funcs: prog
+            .funcs
+            .into_iter()
+            .map(fold_func)
+            .collect::<Result<Vec<_>>>()?,
+    })
+}
+
+fn fold_func(func: TCFunc) -> Result<TCFunc> {
+    Ok(TCFunc {
+        blk: fold_block(func.blk)?,
+        ..func
+    })
+}
+
+fn fold_block(blk: TCBlock) -> Result<TCBlock> {
+    let mut stmts = vec![];
+    for stmt in blk.stmts {
+        if let Some(stmt) = fold_stmt(stmt)? {
+            let done = !falls_through(&stmt);
+            stmts.push(stmt);
+            // nothing after this can run
+            if done {
+                break;
+            }
+        }
+    }
+    Ok(TCBlock {
+        stmts,
+        span: blk.span,
+    })
+}
+
+// the branch of an if that got picked at compile time keeps its own scope
+fn in_block(stmt: TCStmt, span: Span) -> TCStmt {
+    match stmt {
+        TCStmt::Blk(_) => stmt,
+        _ => TCStmt::Blk(TCBlock {
+            stmts: vec![stmt],
+            span,
+        }),
+    }
+}
+
+fn empty_block(span: Span) -> TCStmt {
+    TCStmt::Blk(TCBlock {
+        stmts: vec![],
+        span,
+    })
+}
+
+fn literal_bool(exp: &TypedExp) -> Option<bool> {
+    match exp.exp {
+        TCExp::Literal(Lit::LitBool(b)) => Some(b),
+        _ => None,
+    }
+}
+
+// None means the statement can be dropped entirely
+fn fold_stmt(stmt: TCStmt) -> Result<Option<TCStmt>> {
+    let folded = match stmt {
+        TCStmt::Blk(blk) => TCStmt::Blk(fold_block(blk)?),
+        TCStmt::ReturnStmt(exp) => TCStmt::ReturnStmt(exp.map(fold_exp).transpose()?),
+        TCStmt::VDeclStmt { vdecl, exp } => TCStmt::VDeclStmt {
+            vdecl,
+            exp: fold_exp(exp)?,
+        },
+        TCStmt::ExpStmt(exp) => TCStmt::ExpStmt(fold_exp(exp)?),
+        TCStmt::WhileStmt { cond, stmt } => {
+            let cond = fold_exp(cond)?;
+            if literal_bool(&cond) == Some(false) {
+                return Ok(None);
+            }
+            let span = cond.span;
+            TCStmt::WhileStmt {
+                cond,
+                stmt: Box::new(fold_stmt(*stmt)?.unwrap_or_else(|| empty_block(span))),
+            }
+        }
+        TCStmt::IfStmt {
+            cond,
+            stmt,
+            else_stmt,
+        } => {
+            let cond = fold_exp(cond)?;
+            let span = cond.span;
+            match literal_bool(&cond) {
+                Some(true) => return Ok(fold_stmt(*stmt)?.map(|s| in_block(s, span))),
+                Some(false) => {
+                    return match else_stmt {
+                        Some(else_stmt) => Ok(fold_stmt(*else_stmt)?.map(|s| in_block(s, span))),
+                        None => Ok(None),
+                    }
+                }
+                None => {
+                    let else_stmt = match else_stmt {
+                        Some(else_stmt) => fold_stmt(*else_stmt)?.map(Box::new),
+                        None => None,
+                    };
+                    TCStmt::IfStmt {
+                        cond,
+                        stmt: Box::new(fold_stmt(*stmt)?.unwrap_or_else(|| empty_block(span))),
+                        else_stmt,
+                    }
+                }
+            }
+        }
+        TCStmt::PrintStmt(exp) => TCStmt::PrintStmt(fold_exp(exp)?),
+        TCStmt::PrintStmtSlit(stri) => TCStmt::PrintStmtSlit(stri),
+        TCStmt::Assert(exp) => TCStmt::Assert(fold_exp(exp)?),
+        TCStmt::AssertEq { lhs, rhs } => TCStmt::AssertEq {
+            lhs: fold_exp(lhs)?,
+            rhs: fold_exp(rhs)?,
+        },
+    };
+    Ok(Some(folded))
+}
+
+fn fold_exp(exp: TypedExp) -> Result<TypedExp> {
+    let TypedExp { type_, exp, span } = exp;
+    let folded = match exp {
+        TCExp::Assign { varid, exp } => TCExp::Assign {
+            varid,
+            exp: Box::new(fold_exp(*exp)?),
+        },
+        TCExp::Cast { type_: to, exp } => {
+            let exp = fold_exp(*exp)?;
+            let lit = match &exp.exp {
+                TCExp::Literal(lit) => fold_cast(to, exp.type_, lit),
+                _ => None,
+            };
+            match lit {
+                Some(lit) => TCExp::Literal(lit),
+                None => TCExp::Cast {
+                    type_: to,
+                    exp: Box::new(exp),
+                },
+            }
+        }
+        TCExp::BinOp { op, lhs, rhs } => {
+            let lhs = fold_exp(*lhs)?;
+            let rhs = fold_exp(*rhs)?;
+            let lit = match (&lhs.exp, &rhs.exp) {
+                (TCExp::Literal(l), TCExp::Literal(r)) => fold_binop(&op, lhs.type_, l, r, span)?,
+                _ => None,
+            };
+            match lit {
+                Some(lit) => TCExp::Literal(lit),
+                None => TCExp::BinOp {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            }
+        }
+        TCExp::UnaryOp { op, exp } => {
+            let exp = fold_exp(*exp)?;
+            let lit = match &exp.exp {
+                TCExp::Literal(lit) => fold_unaryop(&op, exp.type_, lit, span)?,
+                _ => None,
+            };
+            match lit {
+                Some(lit) => TCExp::Literal(lit),
+                None => TCExp::UnaryOp {
+                    op,
+                    exp: Box::new(exp),
+                },
+            }
+        }
+        TCExp::FuncCall {
+            globid,
+            exps,
+            expected_args,
+        } => TCExp::FuncCall {
+            globid,
+            exps: exps
+                .into_iter()
+                .map(fold_exp)
+                .collect::<Result<Vec<_>>>()?,
+            expected_args,
+        },
+        TCExp::Literal(lit) => TCExp::Literal(lit),
+        TCExp::VarVal(varid) => TCExp::VarVal(varid),
+        TCExp::NewArray { type_, len } => TCExp::NewArray {
+            type_,
+            len: Box::new(fold_exp(*len)?),
+        },
+        TCExp::Len(array) => TCExp::Len(Box::new(fold_exp(*array)?)),
+        TCExp::Index { varid, index } => TCExp::Index {
+            varid,
+            index: Box::new(fold_exp(*index)?),
+        },
+        TCExp::AssignIndex { varid, index, exp } => TCExp::AssignIndex {
+            varid,
+            index: Box::new(fold_exp(*index)?),
+            exp: Box::new(fold_exp(*exp)?),
+        },
+    };
+    Ok(TypedExp {
+        type_,
+        exp: folded,
+        span,
+    })
+}
+
+fn cint_overflow(span: Span) -> Diagnostic {
+    Diagnostic::new(span, "cint overflow in constant expression")
+}
+
+// a cint op either folds or is an error, a plain int op wraps like the llvm instruction does
+fn int_arith(
+    checked: bool,
+    span: Span,
+    checked_op: fn(i32, i32) -> Option<i32>,
+    wrapping_op: fn(i32, i32) -> i32,
+    a: i32,
+    b: i32,
+) -> Result<Option<Lit>> {
+    if checked {
+        match checked_op(a, b) {
+            Some(n) => Ok(Some(Lit::LitInt(n))),
+            None => Err(cint_overflow(span))?,
+        }
+    } else {
+        Ok(Some(Lit::LitInt(wrapping_op(a, b))))
+    }
+}
+
+// i1 compares are signed in jit.rs, so true is -1 and less than false
+fn bool_as_signed(b: bool) -> i8 {
+    if b {
+        -1
+    } else {
+        0
+    }
+}
+
+pub fn fold_binop(
+    op: &BOp,
+    operand_type: TCType,
+    lhs: &Lit,
+    rhs: &Lit,
+    span: Span,
+) -> Result<Option<Lit>> {
+    let checked = maybe_deref(operand_type)? == TCAtomType::CIntType;
+    Ok(match (lhs, rhs) {
+        (Lit::LitInt(a), Lit::LitInt(b)) => {
+            let (a, b) = (*a, *b);
+            match op {
+                BOp::Add => int_arith(checked, span, i32::checked_add, i32::wrapping_add, a, b)?,
+                BOp::Sub => int_arith(checked, span, i32::checked_sub, i32::wrapping_sub, a, b)?,
+                BOp::Mult => int_arith(checked, span, i32::checked_mul, i32::wrapping_mul, a, b)?,
+                BOp::Div => {
+                    if checked && b == 0 {
+                        Err(Diagnostic::new(
+                            span,
+                            "cint division by zero in constant expression",
+                        ))?
+                    }
+                    match a.checked_div(b) {
+                        Some(n) => Some(Lit::LitInt(n)),
+                        None if checked => Err(cint_overflow(span))?,
+                        // undefined in llvm, leave it for run time
+                        None => None,
+                    }
+                }
+                BOp::EqTo => Some(Lit::LitBool(a == b)),
+                BOp::Gt => Some(Lit::LitBool(a > b)),
+                BOp::Lt => Some(Lit::LitBool(a < b)),
+                BOp::And => Some(Lit::LitInt(a & b)),
+                BOp::Or => Some(Lit::LitInt(a | b)),
+            }
+        }
+        (Lit::LitFloat(a), Lit::LitFloat(b)) => {
+            let (a, b) = (*a, *b);
+            // jit.rs uses unordered compares, which are true whenever either side is NaN
+            let unordered = a.is_nan() || b.is_nan();
+            match op {
+                BOp::Add => Some(Lit::LitFloat(a + b)),
+                BOp::Sub => Some(Lit::LitFloat(a - b)),
+                BOp::Mult => Some(Lit::LitFloat(a * b)),
+                BOp::Div => Some(Lit::LitFloat(a / b)),
+                BOp::EqTo => Some(Lit::LitBool(unordered || a == b)),
+                BOp::Gt => Some(Lit::LitBool(unordered || a > b)),
+                BOp::Lt => Some(Lit::LitBool(unordered || a < b)),
+                BOp::And | BOp::Or => None,
+            }
+        }
+        (Lit::LitBool(a), Lit::LitBool(b)) => {
+            let (a, b) = (*a, *b);
+            match op {
+                BOp::EqTo => Some(Lit::LitBool(a == b)),
+                BOp::Gt => Some(Lit::LitBool(bool_as_signed(a) > bool_as_signed(b))),
+                BOp::Lt => Some(Lit::LitBool(bool_as_signed(a) < bool_as_signed(b))),
+                BOp::And => Some(Lit::LitBool(a && b)),
+                BOp::Or => Some(Lit::LitBool(a || b)),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+pub fn fold_unaryop(op: &UOp, operand_type: TCType, lit: &Lit, span: Span) -> Result<Option<Lit>> {
+    let checked = maybe_deref(operand_type)? == TCAtomType::CIntType;
+    Ok(match (op, lit) {
+        (UOp::SignedNeg, Lit::LitInt(a)) => {
+            int_arith(checked, span, i32::checked_sub, i32::wrapping_sub, 0, *a)?
+        }
+        (UOp::SignedNeg, Lit::LitFloat(a)) => Some(Lit::LitFloat(-a)),
+        (UOp::BitwiseNeg, Lit::LitInt(a)) => Some(Lit::LitInt(!a)),
+        (UOp::BitwiseNeg, Lit::LitBool(b)) => Some(Lit::LitBool(!b)),
+        _ => None,
+    })
+}
+
+// only the casts jit.rs knows how to lift are folded
+pub fn fold_cast(to: TCType, from: TCType, lit: &Lit) -> Option<Lit> {
+    match (to, from, lit) {
+        (TCType::AtomType(TCAtomType::IntType), TCType::AtomType(TCAtomType::IntType), _)
+        | (TCType::AtomType(TCAtomType::CIntType), TCType::AtomType(TCAtomType::IntType), _)
+        | (TCType::AtomType(TCAtomType::FloatType), TCType::AtomType(TCAtomType::FloatType), _)
+        | (TCType::AtomType(TCAtomType::BoolType), TCType::AtomType(TCAtomType::BoolType), _) => {
+            Some(lit.clone())
+        }
+        (TCType::AtomType(TCAtomType::IntType), _, Lit::LitFloat(f))
+        | (TCType::AtomType(TCAtomType::CIntType), _, Lit::LitFloat(f)) => {
+            // fptosi is poison outside of i32's range
+            let truncated = f.trunc();
+            if truncated >= i32::MIN as f64 && truncated <= i32::MAX as f64 {
+                Some(Lit::LitInt(truncated as i32))
+            } else {
+                None
+            }
+        }
+        (TCType::AtomType(TCAtomType::FloatType), _, Lit::LitInt(i)) => {
+            Some(Lit::LitFloat(*i as f64))
+        }
+        _ => None,
+    }
+}
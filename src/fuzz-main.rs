@@ -4,6 +4,7 @@ extern crate lalrpop_util;
 #[macro_use]
 extern crate afl;
 mod ast;
+mod parse;
 mod typecheck;
 
 lalrpop_mod!(pub kaleidoscope); // synthesized by LALRPOP
@@ -12,7 +13,7 @@ lalrpop_mod!(pub kaleidoscope); // synthesized by LALRPOP
 fn main() {
     fuzz!(|data: &[u8]| {
         if let Ok(prog_str) = std::str::from_utf8(data) {
-            let prog = kaleidoscope::ProgParser::new().parse(&prog_str);
+            let prog = parse::parse(&prog_str);
             if let Ok(prog) = prog {
                 let _ = typecheck::typecheck(prog);
             }
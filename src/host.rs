@@ -0,0 +1,192 @@
+use crate::typecheck::{signature, TCAtomType, TCExtern, TCType};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+// a value passed between jitted code and a host function. int and cint are both Int
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    // jitted code hands every argument over as 64 bits, see jit::build_host_stub
+    fn from_bits(type_: TCType, bits: u64) -> Value {
+        match type_ {
+            TCType::AtomType(TCAtomType::FloatType) => Value::Float(f64::from_bits(bits)),
+            TCType::AtomType(TCAtomType::BoolType) => Value::Bool(bits & 1 != 0),
+            _ => Value::Int(bits as i32),
+        }
+    }
+
+    fn to_bits(self) -> u64 {
+        match self {
+            Value::Int(i) => i as i64 as u64,
+            Value::Float(f) => f.to_bits(),
+            Value::Bool(b) => b as u64,
+        }
+    }
+
+    // a command line argument as a value of type_, for calling a function with --entry
+    pub fn parse(type_: TCType, arg: &str) -> Option<Value> {
+        match type_ {
+            TCType::AtomType(TCAtomType::IntType) | TCType::AtomType(TCAtomType::CIntType) => {
+                arg.parse().ok().map(Value::Int)
+            }
+            TCType::AtomType(TCAtomType::FloatType) => arg.parse().ok().map(Value::Float),
+            TCType::AtomType(TCAtomType::BoolType) => arg.parse().ok().map(Value::Bool),
+            _ => None,
+        }
+    }
+
+    fn has_type(self, type_: TCType) -> bool {
+        match (self, type_) {
+            (Value::Int(_), TCType::AtomType(TCAtomType::IntType))
+            | (Value::Int(_), TCType::AtomType(TCAtomType::CIntType))
+            | (Value::Float(_), TCType::AtomType(TCAtomType::FloatType))
+            | (Value::Bool(_), TCType::AtomType(TCAtomType::BoolType)) => true,
+            _ => false,
+        }
+    }
+}
+
+// called with the arguments in the order the extern declares them. returns None for void
+pub type HostCallback = dyn Fn(&[Value]) -> Option<Value>;
+
+pub struct HostFn {
+    pub name: String,
+    pub ret: TCType,
+    pub args: Vec<TCType>,
+    callback: Box<HostCallback>,
+}
+
+// the functions an embedding application exposes to .ek programs. a program calls one by
+// declaring an extern with the same name and signature
+pub struct HostFns {
+    // boxed so jitted code can hold on to a HostFn's address
+    fns: HashMap<String, Box<HostFn>>,
+}
+
+impl HostFns {
+    pub fn new() -> Self {
+        HostFns {
+            fns: HashMap::new(),
+        }
+    }
+
+    // arg and argf, which read the program's command line arguments
+    pub fn with_args(args: Vec<String>) -> Self {
+        let mut host = HostFns::new();
+        let int = TCType::AtomType(TCAtomType::IntType);
+        let float = TCType::AtomType(TCAtomType::FloatType);
+        let int_args = args.clone();
+        host.register("arg", int, &[int], move |vals| {
+            Some(Value::Int(parse_arg(&int_args, vals[0])))
+        })
+        .unwrap();
+        host.register("argf", float, &[int], move |vals| {
+            Some(Value::Float(parse_arg(&args, vals[0])))
+        })
+        .unwrap();
+        host
+    }
+
+    // make callback callable from .ek programs as name. registering a name again replaces it
+    pub fn register<F>(
+        &mut self,
+        name: &str,
+        ret: TCType,
+        args: &[TCType],
+        callback: F,
+    ) -> Result<()>
+    where
+        F: Fn(&[Value]) -> Option<Value> + 'static,
+    {
+        let is_ref = |type_: &TCType| match type_ {
+            TCType::Ref(_, _) | TCType::Array(_) => true,
+            _ => false,
+        };
+        if is_ref(&ret) || args.iter().any(is_ref) {
+            return Err(anyhow!(
+                "host function {} can't take or return ref or array types",
+                name
+            ));
+        }
+        if args.contains(&TCType::VoidType) {
+            return Err(anyhow!("host function {} can't take void arguments", name));
+        }
+        self.fns.insert(
+            name.to_string(),
+            Box::new(HostFn {
+                name: name.to_string(),
+                ret,
+                args: args.to_vec(),
+                callback: Box::new(callback),
+            }),
+        );
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HostFn> {
+        self.fns.get(name).map(|host_fn| host_fn.as_ref())
+    }
+
+    // every extern with the name of a host function has to have its signature too
+    pub fn check_externs(&self, externs: &[TCExtern]) -> Result<()> {
+        for extern_ in externs {
+            if let Some(host_fn) = self.get(&extern_.globid) {
+                if host_fn.ret != extern_.type_ || host_fn.args != extern_.args {
+                    return Err(anyhow!(
+                        "extern {} is declared as {}, but the host function is {}",
+                        extern_.globid,
+                        signature(extern_.type_, &extern_.args),
+                        signature(host_fn.ret, &host_fn.args)
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// what a stub jitted for an extern calls, with the HostFn's address and the arguments.
+// only jitted code calls it, and host_fn and args have to be what the stub passes
+pub unsafe extern "C" fn __host_call__(host_fn: u64, args: *const u64, nargs: i32) -> u64 {
+    let host_fn = &*(host_fn as *const HostFn);
+    let bits = std::slice::from_raw_parts(args, nargs as usize);
+    let vals: Vec<Value> = host_fn
+        .args
+        .iter()
+        .zip(bits)
+        .map(|(type_, bits)| Value::from_bits(*type_, *bits))
+        .collect();
+    match ((host_fn.callback)(&vals), host_fn.ret) {
+        (None, TCType::VoidType) => 0,
+        (Some(val), ret) if val.has_type(ret) => val.to_bits(),
+        (val, ret) => {
+            println!(
+                "error: host function {} returned {:?}, expected {}",
+                host_fn.name, val, ret
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(args: &[String], i: Value) -> T {
+    let i = match i {
+        Value::Int(i) => i,
+        _ => unreachable!("arg index is always an int"),
+    };
+    match args.get(i as usize) {
+        Some(arg) => arg.parse().unwrap_or_else(|_| {
+            println!("error: argument {} is not a number: {}", i, arg);
+            std::process::exit(1);
+        }),
+        _ => {
+            println!("error: argument out of bounds");
+            std::process::exit(1);
+        }
+    }
+}
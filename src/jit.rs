@@ -1,26 +1,42 @@
-use crate::ast::{BOp, Lit, UOp};
+use crate::ast::{BOp, LineIndex, Lit, Span, UOp};
+use crate::dylib::{self, SharedLib};
+use crate::host::{self, HostFn, HostFns, Value};
+use crate::modules;
+use crate::testing;
 use crate::typecheck::{
-    maybe_deref, TCAtomType, TCExp, TCExtern, TCFunc, TCProg, TCStmt, TCType, TypedExp,
+    maybe_deref, TCAtomType, TCExp, TCExtern, TCFunc, TCGlobal, TCProg, TCStmt, TCType, TCVDecl,
+    TypedExp,
+};
+use crate::optimize::{
+    format_timings, run_default_pipeline, run_pipeline, OptLevel, Pipeline, TimingFormat,
 };
-use crate::optimize::{OFlags, run_pipeline, run_default_pipeline};
 use anyhow::{anyhow, Result};
+use inkwell::attributes::{Attribute, AttributeLoc};
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
+use inkwell::debug_info::{
+    AsDIScope, DICompileUnit, DIFlags, DIFlagsConstants, DIScope, DIType, DWARFEmissionKind,
+    DWARFSourceLanguage, DebugInfoBuilder,
+};
 use inkwell::execution_engine::{ExecutionEngine, JitFunction};
-use inkwell::module::{Linkage, Module};
+use inkwell::module::{FlagBehavior, Linkage, Module};
 use inkwell::types::{BasicType, BasicTypeEnum};
-use inkwell::values::{BasicValueEnum, FunctionValue, InstructionOpcode, PointerValue};
+use inkwell::values::{
+    BasicValue, BasicValueEnum, FunctionValue, InstructionOpcode, IntValue, PhiValue, PointerValue,
+};
 use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
+use std::alloc::{alloc_zeroed, dealloc, handle_alloc_error, Layout};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use stopwatch::Stopwatch;
 // may need pub fn set_triple(&self, triple: &TargetTriple)
-// pub fn write_bitcode_to_path(&self, path: &Path) -> bool
 //pub fn write_bitcode_to_memory(&self) -> MemoryBuffer
 
 //pub fn verify(&self) -> Result<(), LLVMString>
-//pub fn link_in_module(&self, other: Self) -> Result<(), LLVMString>
 
 //extern fn printstmt(a: f64, b: f64) -> f64 {
 //    a + b
@@ -33,105 +49,352 @@ pub extern "C" fn kaleido_println() {
 }
 */
 
-static mut CMD_LINE_ARGS: Vec<String> = vec![];
-
-#[no_mangle]
-pub extern "C" fn arg(i: i32) -> i32 {
-    unsafe {
-        if i < CMD_LINE_ARGS.len() as i32 || i < 0 {
-            return CMD_LINE_ARGS[i as usize].parse().unwrap();
-        } else {
-            println!("error: argument out of bounds");
-            std::process::exit(1);
-        }
-    }
+extern "C" fn __printint__(i: i32) {
+    println!("{}", i);
+}
+extern "C" fn __printbool__(b: bool) {
+    println!("{}", b);
+}
+extern "C" fn __printfloat__(f: f64) {
+    println!("{}", f);
+}
+// string literals are global constants in the module, so they survive --emit-bc
+extern "C" fn __printstr__(slit: *const u8, len: i32) {
+    println!("{}", runtime_str(slit, len));
+}
+// codes for the checked cint operations, passed to __cint_overflow__ by the trap block
+const CINT_ADD: u64 = 0;
+const CINT_SUB: u64 = 1;
+const CINT_MUL: u64 = 2;
+const CINT_DIV: u64 = 3;
+
+// every failed cint check in jitted code ends up here
+extern "C" fn __cint_overflow__(op: i32) {
+    let op_name = match op as u64 {
+        CINT_ADD => "add",
+        CINT_SUB => "sub",
+        CINT_MUL => "mul",
+        _ => "div",
+    };
+    println!("error: checked {} overflowed", op_name);
+    std::process::exit(1);
 }
 
-#[no_mangle]
-pub extern "C" fn argf(i: i32) -> f64 {
-    unsafe {
-        if i < CMD_LINE_ARGS.len() as i32 || i < 0 as i32 {
-            return CMD_LINE_ARGS[i as usize].parse().unwrap();
-        } else {
-            println!("error: argument out of bounds");
-            std::process::exit(1);
-        }
-    }
+// failed assert and assert_eq statements. msg is the location and source of the assertion
+extern "C" fn __assert_failed__(msg: *const u8, len: i32) {
+    println!("error: {}", runtime_str(msg, len));
+    std::process::exit(1);
+}
+extern "C" fn __assert_eq_failed_int__(msg: *const u8, len: i32, lhs: i32, rhs: i32) {
+    assert_eq_failed(msg, len, lhs, rhs);
+}
+extern "C" fn __assert_eq_failed_float__(msg: *const u8, len: i32, lhs: f64, rhs: f64) {
+    assert_eq_failed(msg, len, lhs, rhs);
+}
+extern "C" fn __assert_eq_failed_bool__(msg: *const u8, len: i32, lhs: bool, rhs: bool) {
+    assert_eq_failed(msg, len, lhs, rhs);
 }
 
-#[no_mangle]
-pub extern "C" fn __printint__(i: i32) {
-    println!("{}", i);
+fn assert_eq_failed<T: std::fmt::Display>(msg: *const u8, len: i32, lhs: T, rhs: T) {
+    println!("error: {}", runtime_str(msg, len));
+    println!("  left: {}", lhs);
+    println!(" right: {}", rhs);
+    std::process::exit(1);
 }
-#[no_mangle]
-pub extern "C" fn __printbool__(b: bool) {
-    println!("{}", b);
+
+// arrays are a header and then their elements, zeroed when allocated. jitted code holds
+// pointers to the header, reads len and the elements itself and calls the functions below for
+// the rest
+#[repr(C)]
+struct ArrayHeader {
+    refs: usize,
+    len: i32,
+    elem_size: i32,
 }
-#[no_mangle]
-pub extern "C" fn __printfloat__(f: f64) {
-    println!("{}", f);
+
+// where jitted code finds len and the first element, in bytes from the start of the header
+const ARRAY_LEN_OFFSET: u64 = std::mem::size_of::<usize>() as u64;
+const ARRAY_DATA_OFFSET: u64 = std::mem::size_of::<ArrayHeader>() as u64;
+
+// arrays allocated and not freed yet, which a test has to leave at 0
+static LIVE_ARRAYS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn live_arrays() -> usize {
+    LIVE_ARRAYS.load(Ordering::SeqCst)
 }
-#[no_mangle]
-pub extern "C" fn __printstr__(slit: u64) {
-    let stri_bytes = unsafe { &*(slit as *const String) };
-    println!("{}", stri_bytes);
+
+fn array_layout(len: i32, elem_size: i32) -> Layout {
+    let size = std::mem::size_of::<ArrayHeader>() + len as usize * elem_size as usize;
+    Layout::from_size_align(size, 8).unwrap()
 }
-#[no_mangle]
-pub extern "C" fn __sadd__(a: i32, b: i32) -> i32 {
-    if let Some(ans) = a.checked_add(b) {
-        ans
-    } else {
-        println!("error: checked add overflowed");
+
+// new T[len], holding one reference. msg is the location of the new
+extern "C" fn __array_new__(len: i32, elem_size: i32, msg: *const u8, msg_len: i32) -> *mut u8 {
+    if len < 0 {
+        println!("error: {}: array length {} is negative", runtime_str(msg, msg_len), len);
         std::process::exit(1);
     }
+    let layout = array_layout(len, elem_size);
+    let array = unsafe { alloc_zeroed(layout) };
+    if array.is_null() {
+        handle_alloc_error(layout);
+    }
+    let header = ArrayHeader {
+        refs: 1,
+        len,
+        elem_size,
+    };
+    unsafe { (array as *mut ArrayHeader).write(header) };
+    LIVE_ARRAYS.fetch_add(1, Ordering::SeqCst);
+    array
+}
+// what a failed bounds check calls. msg is the location of the indexing
+extern "C" fn __array_out_of_bounds__(index: i32, len: i32, msg: *const u8, msg_len: i32) {
+    println!(
+        "error: {}: index {} is out of bounds for an array of length {}",
+        runtime_str(msg, msg_len),
+        index,
+        len
+    );
+    std::process::exit(1);
+}
+extern "C" fn __array_retain__(array: *mut u8) {
+    unsafe { (*(array as *mut ArrayHeader)).refs += 1 };
+}
+// frees the array when this was the last reference to it
+extern "C" fn __array_release__(array: *mut u8) {
+    let header = unsafe { &mut *(array as *mut ArrayHeader) };
+    header.refs -= 1;
+    if header.refs == 0 {
+        let layout = array_layout(header.len, header.elem_size);
+        unsafe { dealloc(array, layout) };
+        LIVE_ARRAYS.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
-#[no_mangle]
-pub extern "C" fn __ssub__(a: i32, b: i32) -> i32 {
-    if let Some(ans) = a.checked_sub(b) {
-        ans
-    } else {
-        println!("error: checked sub overflowed");
-        std::process::exit(1);
+fn runtime_str(slit: *const u8, len: i32) -> String {
+    let bytes = unsafe { std::slice::from_raw_parts(slit, len as usize) };
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+// the runtime functions above by the names jitted code calls them by. these are found before
+// anything in a --link-lib library
+fn runtime_symbols() -> HashMap<&'static str, usize> {
+    let mut symbols = HashMap::new();
+    symbols.insert("__printint__", __printint__ as usize);
+    symbols.insert("__printbool__", __printbool__ as usize);
+    symbols.insert("__printfloat__", __printfloat__ as usize);
+    symbols.insert("__printstr__", __printstr__ as usize);
+    symbols.insert("__cint_overflow__", __cint_overflow__ as usize);
+    symbols.insert("__assert_failed__", __assert_failed__ as usize);
+    symbols.insert("__assert_eq_failed_int__", __assert_eq_failed_int__ as usize);
+    symbols.insert("__assert_eq_failed_float__", __assert_eq_failed_float__ as usize);
+    symbols.insert("__assert_eq_failed_bool__", __assert_eq_failed_bool__ as usize);
+    symbols.insert("__array_new__", __array_new__ as usize);
+    symbols.insert("__array_out_of_bounds__", __array_out_of_bounds__ as usize);
+    symbols.insert("__array_retain__", __array_retain__ as usize);
+    symbols.insert("__array_release__", __array_release__ as usize);
+    symbols.insert("__host_call__", host::__host_call__ as usize);
+    symbols
+}
+
+// point every function the module calls but doesn't define at its address, or give it a body
+// calling the host function of the same name. ExternalWeak declarations nothing defines would
+// otherwise be jitted as null and crash when called
+fn resolve_externs<'ctx>(
+    context: &'ctx Context,
+    module: &Module<'ctx>,
+    execution_engine: &ExecutionEngine,
+    host: &HostFns,
+    libs: &[SharedLib],
+) -> Result<()> {
+    let runtime = runtime_symbols();
+    let mut next_fn = module.get_first_function();
+    while let Some(fn_) = next_fn {
+        next_fn = fn_.get_next_function();
+        let name = fn_.get_name().to_str()?;
+        let unused = fn_.as_global_value().as_pointer_value().get_first_use().is_none();
+        if fn_.count_basic_blocks() > 0 || name.starts_with("llvm.") || unused {
+            continue;
+        }
+        if let Some(host_fn) = host.get(name) {
+            build_host_stub(context, module, fn_, host_fn)?;
+            continue;
+        }
+        let addr = match runtime.get(name) {
+            Some(addr) => *addr,
+            None => dylib::lookup(libs, name).ok_or(anyhow!("unresolved extern '{}'", name))?,
+        };
+        execution_engine.add_global_mapping(&fn_, addr);
     }
+    Ok(())
 }
 
-#[no_mangle]
-pub extern "C" fn __smul__(a: i32, b: i32) -> i32 {
-    if let Some(ans) = a.checked_mul(b) {
-        ans
-    } else {
-        println!("error: checked mul overflowed");
-        std::process::exit(1);
+// the body of an extern implemented by host_fn: spill the arguments into an array of i64s and
+// hand it to __host_call__ along with host_fn's address, then turn the i64 it returns back
+// into the extern's return type. see host::Value for how each type fits in 64 bits
+fn build_host_stub<'ctx>(
+    context: &'ctx Context,
+    module: &Module<'ctx>,
+    fn_: FunctionValue<'ctx>,
+    host_fn: &HostFn,
+) -> Result<()> {
+    let i64_type = context.i64_type();
+    let llvm_type = |type_: &TCType| -> Option<BasicTypeEnum<'ctx>> {
+        match type_ {
+            TCType::AtomType(TCAtomType::FloatType) => Some(context.f64_type().into()),
+            TCType::AtomType(TCAtomType::BoolType) => Some(context.bool_type().into()),
+            TCType::VoidType => None,
+            _ => Some(context.i32_type().into()),
+        }
+    };
+    // a linked bitcode file was never checked against the host, so check here
+    let arg_types: Vec<BasicTypeEnum> = host_fn.args.iter().filter_map(llvm_type).collect();
+    let expected = match llvm_type(&host_fn.ret) {
+        Some(ret) => ret.fn_type(&arg_types, false),
+        None => context.void_type().fn_type(&arg_types, false),
+    };
+    if fn_.get_type() != expected {
+        return Err(anyhow!(
+            "extern {} doesn't match the signature of the host function",
+            host_fn.name
+        ));
+    }
+
+    let host_call = match module.get_function("__host_call__") {
+        Some(host_call) => host_call,
+        None => {
+            let fn_type = i64_type.fn_type(
+                &[
+                    i64_type.into(),
+                    i64_type.ptr_type(AddressSpace::Generic).into(),
+                    context.i32_type().into(),
+                ],
+                false,
+            );
+            module.add_function("__host_call__", fn_type, Some(Linkage::External))
+        }
+    };
+
+    let bldr = context.create_builder();
+    bldr.position_at_end(context.append_basic_block(fn_, "entry"));
+    let nargs = host_fn.args.len() as u64;
+    let args = bldr.build_array_alloca(i64_type, i64_type.const_int(nargs.max(1), false), "args");
+    for (i, (param, type_)) in fn_.get_param_iter().zip(&host_fn.args).enumerate() {
+        let bits = match type_ {
+            TCType::AtomType(TCAtomType::FloatType) => bldr
+                .build_bitcast(param, i64_type, "bits")
+                .into_int_value(),
+            TCType::AtomType(TCAtomType::BoolType) => {
+                bldr.build_int_z_extend(param.into_int_value(), i64_type, "bits")
+            }
+            _ => bldr.build_int_s_extend(param.into_int_value(), i64_type, "bits"),
+        };
+        let slot = unsafe {
+            bldr.build_in_bounds_gep(args, &[i64_type.const_int(i as u64, false)], "slot")
+        };
+        bldr.build_store(slot, bits);
     }
+    let host_fn_addr = i64_type.const_int(host_fn as *const HostFn as u64, false);
+    let nargs = context.i32_type().const_int(nargs, false);
+    let bits = bldr
+        .build_call(host_call, &[host_fn_addr.into(), args.into(), nargs.into()], "call")
+        .try_as_basic_value()
+        .left()
+        .unwrap()
+        .into_int_value();
+    match host_fn.ret {
+        TCType::VoidType => bldr.build_return(None),
+        TCType::AtomType(TCAtomType::FloatType) => {
+            let ret = bldr.build_bitcast(bits, context.f64_type(), "ret");
+            bldr.build_return(Some(&ret))
+        }
+        TCType::AtomType(TCAtomType::BoolType) => {
+            let ret = bldr.build_int_truncate(bits, context.bool_type(), "ret");
+            bldr.build_return(Some(&ret))
+        }
+        _ => {
+            let ret = bldr.build_int_truncate(bits, context.i32_type(), "ret");
+            bldr.build_return(Some(&ret))
+        }
+    };
+    fn_.set_linkage(Linkage::Internal);
+    Ok(())
 }
 
-#[no_mangle]
-pub extern "C" fn __sdiv__(a: i32, b: i32) -> i32 {
-    if let Some(ans) = a.checked_div(b) {
-        ans
-    } else {
-        println!("error: checked div overflowed");
-        std::process::exit(1);
+// dwarf type encodings (llvm/BinaryFormat/Dwarf.def), llvm-sys doesn't export names for these
+const DW_ATE_BOOLEAN: u32 = 0x02;
+const DW_ATE_FLOAT: u32 = 0x04;
+const DW_ATE_SIGNED: u32 = 0x05;
+
+// everything needed to emit dwarf for -g.
+// scopes holds the subprogram of the function being lifted followed by one lexical block per
+// nested TCStmt::Blk, innermost last
+struct DebugInfo<'ctx> {
+    builder: DebugInfoBuilder<'ctx>,
+    compile_unit: DICompileUnit<'ctx>,
+    lines: LineIndex,
+    scopes: Vec<DIScope<'ctx>>,
+}
+
+// the compile unit and builder for one source file's llvm module, used with -g
+fn create_debug_info<'ctx>(
+    context: &'ctx Context,
+    module: &Module<'ctx>,
+    module_name: &str,
+    src: &str,
+) -> DebugInfo<'ctx> {
+    // gdb finds the .ek file through the compile unit, so give it an absolute path
+    let path = std::fs::canonicalize(module_name)
+        .unwrap_or_else(|_| Path::new(module_name).to_path_buf());
+    let filename = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(module_name);
+    let directory = path.parent().and_then(|d| d.to_str()).unwrap_or(".");
+    module.add_basic_value_flag(
+        "Debug Info Version",
+        FlagBehavior::Warning,
+        context.i32_type().const_int(3, false),
+    );
+    let (builder, compile_unit) = module.create_debug_info_builder(
+        true,
+        DWARFSourceLanguage::C,
+        filename,
+        directory,
+        "ekcc",
+        false,
+        "",
+        0,
+        "",
+        DWARFEmissionKind::Full,
+        0,
+        false,
+        false,
+    );
+    DebugInfo {
+        builder,
+        compile_unit,
+        lines: LineIndex::new(src),
+        scopes: vec![],
     }
 }
 
-// making sure rustc doesn't remove arg and argf
-#[used]
-static EXTERNAL_FNS1: [extern "C" fn(i32) -> i32; 1] = [arg];
-
-#[used]
-static EXTERNAL_FNS2: [extern "C" fn(i32) -> f64; 1] = [argf];
-#[used]
-static EXTERNAL_FNS3: [extern "C" fn(i32); 1] = [__printint__];
-#[used]
-static EXTERNAL_FNS4: [extern "C" fn(bool); 1] = [__printbool__];
-#[used]
-static EXTERNAL_FNS5: [extern "C" fn(f64); 1] = [__printfloat__];
-#[used]
-static EXTERNAL_FNS6: [extern "C" fn(u64); 1] = [__printstr__];
-#[used]
-static EXTERNAL_FNS7: [extern "C" fn(i32, i32) -> i32; 4] = [__sadd__, __ssub__, __smul__, __sdiv__];
+struct SourceFile {
+    filename: String,
+    src: String,
+    lines: LineIndex,
+}
+
+impl SourceFile {
+    fn new(filename: &str, src: &str) -> Self {
+        SourceFile {
+            filename: filename.to_string(),
+            src: src.to_string(),
+            lines: LineIndex::new(src),
+        }
+    }
+}
 
 struct JitDoer<'ctx> {
     context: &'ctx Context,
@@ -141,15 +404,40 @@ struct JitDoer<'ctx> {
     // is this necessary to store...?
     current_fn_being_compiled: Option<FunctionValue<'ctx>>,
     current_fn_stack_variables: HashMap<String, (PointerValue<'ctx>, TCType)>,
+    // the globals of the file being lifted, which every function starts out with in scope
+    globals: HashMap<String, (PointerValue<'ctx>, TCType)>,
+    debug_info: Option<DebugInfo<'ctx>>,
+    // the file being lifted, for the locations in assertion failures
+    source: SourceFile,
+    // block every failed cint check in the current function branches to, made on first use.
+    // the phi picks up which operation failed
+    cint_trap: Cell<Option<(BasicBlock<'ctx>, PhiValue<'ctx>)>>,
+    // the same for failed bounds checks, with phis for the index, the length and the location
+    array_trap: Cell<Option<(BasicBlock<'ctx>, [PhiValue<'ctx>; 4])>>,
+    // slots of the array variables declared in each scope of the current function, innermost
+    // last. each holds a reference, released when its scope is left
+    array_scopes: Vec<Vec<PointerValue<'ctx>>>,
 }
 
 impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
-    fn init(context: &'ctx Context, module_name: &str, opt_lvl: OptimizationLevel) -> Result<Self> {
+    // src is the source text of module_name, debug is -g
+    fn init(
+        context: &'ctx Context,
+        module_name: &str,
+        src: &str,
+        opt_lvl: OptimizationLevel,
+        debug: bool,
+    ) -> Result<Self> {
         let module = context.create_module(module_name);
         let execution_engine = module
             .create_jit_execution_engine(opt_lvl)
             .expect("error! cannot create jit execution engine");
         let main_builder = context.create_builder();
+        let debug_info = if debug {
+            Some(create_debug_info(context, &module, module_name, src))
+        } else {
+            None
+        };
         let ret = Self {
             context,
             module,
@@ -157,12 +445,231 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             execution_engine,
             current_fn_being_compiled: None,
             current_fn_stack_variables: HashMap::new(),
+            globals: HashMap::new(),
+            debug_info,
+            source: SourceFile::new(module_name, src),
+            cint_trap: Cell::new(None),
+            array_trap: Cell::new(None),
+            array_scopes: vec![],
         };
         ret.gen_print_externs();
-        ret.gen_signed_extern();
+        ret.gen_cint_externs();
+        ret.gen_assert_externs();
+        ret.gen_array_externs();
         Ok(ret)
     }
 
+    // must run after every function is lifted and before the module is optimized or jitted
+    fn finalize_debug_info(&self) {
+        if let Some(di) = &self.debug_info {
+            di.builder.finalize();
+        }
+    }
+
+    // lift every extern and function of one file into the current module
+    fn lift_items(&mut self, ast: TCProg) -> Result<()> {
+        for e in ast.externs {
+            self.lift_extern(e)?;
+        }
+        self.globals = HashMap::new();
+        for g in ast.globals {
+            self.lift_global(g);
+        }
+        for f in ast.funcs.iter() {
+            self.declare_function(f)?;
+        }
+        for f in ast.funcs {
+            self.lift_function(f)?;
+        }
+        self.finalize_debug_info();
+        Ok(())
+    }
+
+    // a function like run that calls the --entry function with constant arguments and prints
+    // what it returns. the entry function has to be lifted already
+    fn build_entry_wrapper(&self, name: &str, ret: TCType, args: &[Value]) {
+        let func = self.module.get_function(name).unwrap();
+        let fn_type = self.context.i32_type().fn_type(&[], false);
+        let wrapper = self.module.add_function(ENTRY_WRAPPER, fn_type, None);
+        let bldr = self.context.create_builder();
+        bldr.position_at_end(self.context.append_basic_block(wrapper, "entry"));
+        let args: Vec<BasicValueEnum> = args
+            .iter()
+            .map(|arg| match arg {
+                Value::Int(i) => self.context.i32_type().const_int(*i as u64, true).into(),
+                Value::Float(f) => self.context.f64_type().const_float(*f).into(),
+                Value::Bool(b) => self.context.bool_type().const_int(*b as u64, false).into(),
+            })
+            .collect();
+        let call = bldr.build_call(func, &args, "call");
+        let print_fn = match ret {
+            TCType::AtomType(TCAtomType::FloatType) => Some("__printfloat__"),
+            TCType::AtomType(TCAtomType::BoolType) => Some("__printbool__"),
+            TCType::AtomType(_) => Some("__printint__"),
+            _ => None,
+        };
+        if let (Some(print_fn), Some(result)) = (print_fn, call.try_as_basic_value().left()) {
+            let print_fn = self.module.get_function(print_fn).unwrap();
+            bldr.build_call(print_fn, &[result], "call");
+        }
+        bldr.build_return(Some(&self.context.i32_type().const_int(0, false)));
+    }
+
+    // lift an imported file into an llvm module of its own, then link that into the main one.
+    // its module::name functions replace the extern declarations importers made for them
+    fn lift_import(&mut self, import: TCModule, debug: bool) -> Result<()> {
+        let module = self.context.create_module(&import.filename);
+        let debug_info = if debug {
+            Some(create_debug_info(
+                self.context,
+                &module,
+                &import.filename,
+                &import.src,
+            ))
+        } else {
+            None
+        };
+        let main_module = std::mem::replace(&mut self.module, module);
+        let main_debug_info = std::mem::replace(&mut self.debug_info, debug_info);
+        let source = SourceFile::new(&import.filename, &import.src);
+        let main_source = std::mem::replace(&mut self.source, source);
+        self.gen_print_externs();
+        self.gen_cint_externs();
+        self.gen_assert_externs();
+        self.gen_array_externs();
+        let lifted = self.lift_items(import.prog);
+        let module = std::mem::replace(&mut self.module, main_module);
+        self.debug_info = main_debug_info;
+        self.source = main_source;
+        lifted?;
+        self.module
+            .link_in_module(module)
+            .map_err(|msg| anyhow!("could not link {}: {}", import.filename, msg))
+    }
+
+    fn debug_atom_type(&self, di: &DebugInfo<'ctx>, type_: TCAtomType) -> Result<DIType<'ctx>> {
+        let (name, size_in_bits, encoding) = match type_ {
+            TCAtomType::IntType => ("int", 32, DW_ATE_SIGNED),
+            TCAtomType::CIntType => ("cint", 32, DW_ATE_SIGNED),
+            TCAtomType::FloatType => ("float", 64, DW_ATE_FLOAT),
+            TCAtomType::BoolType => ("bool", 8, DW_ATE_BOOLEAN),
+        };
+        let basic_type = di
+            .builder
+            .create_basic_type(name, size_in_bits, encoding, DIFlags::PUBLIC)
+            .map_err(|msg| anyhow!("could not create debug type {}: {}", name, msg))?;
+        Ok(basic_type.as_type())
+    }
+
+    fn debug_type(&self, di: &DebugInfo<'ctx>, type_: TCType) -> Result<Option<DIType<'ctx>>> {
+        Ok(match type_ {
+            TCType::AtomType(atom) => Some(self.debug_atom_type(di, atom)?),
+            TCType::VoidType => None,
+            TCType::Ref(_, atom) | TCType::Array(atom) => {
+                let name = match type_ {
+                    TCType::Array(_) => "array",
+                    _ => "ref",
+                };
+                let pointee = self.debug_atom_type(di, atom)?;
+                let pointer_size = self
+                    .execution_engine
+                    .get_target_data()
+                    .get_pointer_byte_size(None)
+                    * 8;
+                Some(
+                    di.builder
+                        .create_pointer_type(
+                            name,
+                            pointee,
+                            pointer_size as u64,
+                            pointer_size,
+                            AddressSpace::Generic,
+                        )
+                        .as_type(),
+                )
+            }
+        })
+    }
+
+    // attach a subprogram to fn_ and make it the scope for everything lifted until the next one
+    fn debug_enter_function(&mut self, fn_: FunctionValue<'ctx>, func: &TCFunc) -> Result<()> {
+        let di = match &self.debug_info {
+            Some(di) => di,
+            None => return Ok(()),
+        };
+        let ret_type = self.debug_type(di, func.type_)?;
+        let arg_types = func
+            .args
+            .iter()
+            .map(|x| {
+                self.debug_type(di, x.type_)
+                    .and_then(|result| result.ok_or(anyhow!("func args cannot have void type")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let file = di.compile_unit.get_file();
+        let (line, _) = di.lines.line_col(func.span.lo);
+        let subroutine_type = di.builder.create_subroutine_type(
+            file,
+            ret_type,
+            arg_types.as_slice(),
+            DIFlags::PUBLIC,
+        );
+        let subprogram = di.builder.create_function(
+            di.compile_unit.as_debug_info_scope(),
+            &func.globid,
+            None,
+            file,
+            line,
+            subroutine_type,
+            false,
+            true,
+            line,
+            DIFlags::PUBLIC,
+            false,
+        );
+        fn_.set_subprogram(subprogram);
+        if let Some(di) = &mut self.debug_info {
+            di.scopes = vec![subprogram.as_debug_info_scope()];
+        }
+        self.set_debug_location(func.span);
+        Ok(())
+    }
+
+    fn debug_enter_block(&mut self, span: Span) {
+        if let Some(di) = &mut self.debug_info {
+            let (line, col) = di.lines.line_col(span.lo);
+            let parent = *di.scopes.last().expect("lexical block outside of a function");
+            let block = di.builder.create_lexical_block(
+                parent,
+                di.compile_unit.get_file(),
+                line,
+                col,
+            );
+            di.scopes.push(block.as_debug_info_scope());
+        }
+    }
+
+    fn debug_exit_block(&mut self) {
+        if let Some(di) = &mut self.debug_info {
+            di.scopes.pop();
+        }
+    }
+
+    // everything built from here on is attributed to span's line and column
+    fn set_debug_location(&self, span: Span) {
+        if let Some(di) = &self.debug_info {
+            if let Some(scope) = di.scopes.last() {
+                let (line, col) = di.lines.line_col(span.lo);
+                let loc = di
+                    .builder
+                    .create_debug_location(self.context, line, col, *scope, None);
+                self.main_builder
+                    .set_current_debug_location(self.context, loc);
+            }
+        }
+    }
+
     fn gen_print_externs(&self) {
         let void_type = self.context.void_type();
         let todo: Vec<(&str, BasicTypeEnum)> = vec![
@@ -177,8 +684,11 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                 .add_function(fn_name, fn_type, Some(Linkage::ExternalWeak));
         }
         let fn_name = "__printstr__";
-        let target_data = self.execution_engine.get_target_data();
-        let ptr_type = self.context.ptr_sized_int_type(&target_data, None).into();
+        let ptr_type = self
+            .context
+            .i8_type()
+            .ptr_type(AddressSpace::Generic)
+            .into();
         let vec_size_type = self.context.i32_type().into();
 
         let args: Vec<BasicTypeEnum> = vec![ptr_type, vec_size_type];
@@ -187,27 +697,366 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             .add_function(fn_name, fn_type, Some(Linkage::ExternalWeak));
     }
 
-    fn gen_signed_extern(&self) {
+    // the overflow intrinsics used for cint arithmetic, and the routine their checks trap to
+    fn gen_cint_externs(&self) {
         let i32_type = self.context.i32_type();
+        let result_type = self
+            .context
+            .struct_type(&[i32_type.into(), self.context.bool_type().into()], false);
         let args: Vec<BasicTypeEnum> = vec![i32_type.into(), i32_type.into()];
-        let fn_type = i32_type.fn_type(args.as_slice(), false);
-        self.module
-            .add_function("__sadd__", fn_type, Some(Linkage::ExternalWeak));
-        self.module
-            .add_function("__ssub__", fn_type, Some(Linkage::ExternalWeak));
-        self.module
-            .add_function("__smul__", fn_type, Some(Linkage::ExternalWeak));
-        self.module
-            .add_function("__sdiv__", fn_type, Some(Linkage::ExternalWeak));
+        let fn_type = result_type.fn_type(args.as_slice(), false);
+        for intrinsic in [
+            "llvm.sadd.with.overflow.i32",
+            "llvm.ssub.with.overflow.i32",
+            "llvm.smul.with.overflow.i32",
+        ]
+        .iter()
+        {
+            self.module.add_function(intrinsic, fn_type, None);
+        }
+
+        let trap_type = self
+            .context
+            .void_type()
+            .fn_type(&[i32_type.into()], false);
+        let trap_fn =
+            self.module
+                .add_function("__cint_overflow__", trap_type, Some(Linkage::ExternalWeak));
+        // lets llvm treat the trap block as the unlikely path
+        for attr in ["noreturn", "cold"].iter() {
+            let kind_id = Attribute::get_named_enum_kind_id(attr);
+            trap_fn.add_attribute(
+                AttributeLoc::Function,
+                self.context.create_enum_attribute(kind_id, 0),
+            );
+        }
+    }
+
+    // what failed assert and assert_eq statements call, with a message and the two values
+    fn gen_assert_externs(&self) {
+        let msg_type: BasicTypeEnum = self.context.i8_type().ptr_type(AddressSpace::Generic).into();
+        let len_type: BasicTypeEnum = self.context.i32_type().into();
+        let todo: Vec<(&str, Vec<BasicTypeEnum>)> = vec![
+            ("__assert_failed__", vec![]),
+            ("__assert_eq_failed_int__", vec![self.context.i32_type().into(); 2]),
+            ("__assert_eq_failed_float__", vec![self.context.f64_type().into(); 2]),
+            ("__assert_eq_failed_bool__", vec![self.context.bool_type().into(); 2]),
+        ];
+        for (fn_name, values) in todo {
+            let mut args = vec![msg_type, len_type];
+            args.extend(values);
+            let fn_type = self.context.void_type().fn_type(&args, false);
+            let fn_ =
+                self.module
+                    .add_function(fn_name, fn_type, Some(Linkage::ExternalWeak));
+            for attr in ["noreturn", "cold"].iter() {
+                let kind_id = Attribute::get_named_enum_kind_id(attr);
+                fn_.add_attribute(
+                    AttributeLoc::Function,
+                    self.context.create_enum_attribute(kind_id, 0),
+                );
+            }
+        }
+    }
+
+    // the runtime functions behind arrays, see ArrayHeader
+    fn gen_array_externs(&self) {
+        let array_type: BasicTypeEnum =
+            self.context.i8_type().ptr_type(AddressSpace::Generic).into();
+        let i32_type: BasicTypeEnum = self.context.i32_type().into();
+        let void_type = self.context.void_type();
+        let todo = vec![
+            (
+                "__array_new__",
+                array_type.fn_type(&[i32_type, i32_type, array_type, i32_type], false),
+            ),
+            ("__array_retain__", void_type.fn_type(&[array_type], false)),
+            ("__array_release__", void_type.fn_type(&[array_type], false)),
+        ];
+        for (fn_name, fn_type) in todo {
+            self.module
+                .add_function(fn_name, fn_type, Some(Linkage::ExternalWeak));
+        }
+
+        let trap_type = void_type.fn_type(&[i32_type, i32_type, array_type, i32_type], false);
+        let trap_fn = self.module.add_function(
+            "__array_out_of_bounds__",
+            trap_type,
+            Some(Linkage::ExternalWeak),
+        );
+        for attr in ["noreturn", "cold"].iter() {
+            let kind_id = Attribute::get_named_enum_kind_id(attr);
+            trap_fn.add_attribute(
+                AttributeLoc::Function,
+                self.context.create_enum_attribute(kind_id, 0),
+            );
+        }
+    }
+
+    fn build_array_call(
+        &self,
+        fn_name: &str,
+        args: &[BasicValueEnum<'ctx>],
+    ) -> Option<BasicValueEnum<'ctx>> {
+        let func = self.module.get_function(fn_name).unwrap();
+        self.main_builder
+            .build_call(func, args, "call")
+            .try_as_basic_value()
+            .left()
+    }
+
+    fn retain_array(&self, array: BasicValueEnum<'ctx>) {
+        self.build_array_call("__array_retain__", &[array]);
+    }
+
+    fn release_array(&self, array: BasicValueEnum<'ctx>) {
+        self.build_array_call("__array_release__", &[array]);
+    }
+
+    // pointer to byte offset of array, cast to a pointer to type_
+    fn array_field(
+        &self,
+        array: PointerValue<'ctx>,
+        offset: u64,
+        type_: BasicTypeEnum<'ctx>,
+        name: &str,
+    ) -> PointerValue<'ctx> {
+        let offset = self.context.i64_type().const_int(offset, false);
+        let field = unsafe {
+            self.main_builder
+                .build_in_bounds_gep(array, &[offset], name)
+        };
+        self.main_builder
+            .build_pointer_cast(field, type_.ptr_type(AddressSpace::Generic), name)
+    }
+
+    fn build_array_len(&self, array: PointerValue<'ctx>) -> IntValue<'ctx> {
+        let i32_type = self.context.i32_type().into();
+        let len_ptr = self.array_field(array, ARRAY_LEN_OFFSET, i32_type, "len_ptr");
+        self.main_builder
+            .build_load(len_ptr, "len")
+            .into_int_value()
+    }
+
+    // drop what the array variables of scopes hold, on the way out of them
+    fn release_arrays(&self, scopes: &[Vec<PointerValue<'ctx>>]) {
+        for slot in scopes.iter().flatten() {
+            let array = self.main_builder.build_load(*slot, "array");
+            self.release_array(array);
+        }
+    }
+
+    // file:line:col
+    fn location(&self, span: Span) -> String {
+        let (line, col) = self.source.lines.line_col(span.lo);
+        format!("{}:{}:{}", self.source.filename, line, col)
+    }
+
+    // the location of span as the message and length arguments of a runtime function
+    fn location_args(&self, span: Span) -> [BasicValueEnum<'ctx>; 2] {
+        let msg = self.location(span);
+        let msg_ptr = self
+            .main_builder
+            .build_global_string_ptr(&msg, "location")
+            .as_pointer_value()
+            .into();
+        let msg_len = self
+            .context
+            .i32_type()
+            .const_int(msg.len() as u64, false)
+            .into();
+        [msg_ptr, msg_len]
+    }
+
+    // file:line:col: assertion failed: <the asserted source>
+    fn assert_message(&self, span: Span, asserted: &str) -> String {
+        format!("{}: assertion failed: {}", self.location(span), asserted)
+    }
+
+    fn source_text(&self, span: Span) -> &str {
+        &self.source.src[span.lo..span.hi]
+    }
+
+    // call failed_fn with msg and values if ok is false, and keep building after the check
+    // otherwise
+    fn build_assert(
+        &self,
+        ok: IntValue<'ctx>,
+        msg: &str,
+        failed_fn: &str,
+        values: &[BasicValueEnum<'ctx>],
+    ) {
+        let check_bb = self.main_builder.get_insert_block().unwrap();
+        let ok_bb = self.context.insert_basic_block_after(check_bb, "assert_ok");
+        let failed_bb = self.context.insert_basic_block_after(check_bb, "assert_failed");
+        self.main_builder
+            .build_conditional_branch(ok, ok_bb, failed_bb);
+
+        self.main_builder.position_at_end(failed_bb);
+        let msg_ptr: BasicValueEnum = self
+            .main_builder
+            .build_global_string_ptr(msg, "assert_msg")
+            .as_pointer_value()
+            .into();
+        let msg_len: BasicValueEnum = self
+            .context
+            .i32_type()
+            .const_int(msg.len() as u64, false)
+            .into();
+        let mut args = vec![msg_ptr, msg_len];
+        args.extend_from_slice(values);
+        let func = self.module.get_function(failed_fn).unwrap();
+        self.main_builder.build_call(func, &args, "call");
+        self.main_builder.build_unreachable();
+
+        self.main_builder.position_at_end(ok_bb);
     }
 
+    fn cint_trap(&self) -> (BasicBlock<'ctx>, PhiValue<'ctx>) {
+        if let Some(trap) = self.cint_trap.get() {
+            return trap;
+        }
+        let i32_type = self.context.i32_type();
+        let trap_bb = self
+            .context
+            .append_basic_block(self.current_fn_being_compiled.unwrap(), "cint_trap");
+        let bldr = self.context.create_builder();
+        bldr.position_at_end(trap_bb);
+        let op = bldr.build_phi(i32_type, "op");
+        let trap_fn = self.module.get_function("__cint_overflow__").unwrap();
+        bldr.build_call(trap_fn, &[op.as_basic_value()], "call");
+        bldr.build_unreachable();
+        self.cint_trap.set(Some((trap_bb, op)));
+        (trap_bb, op)
+    }
+
+    // branch to the trap block if failed is true, and keep building after the check otherwise
+    fn build_cint_check(&self, failed: IntValue<'ctx>, op: u64) {
+        let (trap_bb, op_phi) = self.cint_trap();
+        let check_bb = self.main_builder.get_insert_block().unwrap();
+        let ok_bb = self.context.insert_basic_block_after(check_bb, "cint_ok");
+        self.main_builder
+            .build_conditional_branch(failed, trap_bb, ok_bb);
+        op_phi.add_incoming(&[(&self.context.i32_type().const_int(op, false), check_bb)]);
+        self.main_builder.position_at_end(ok_bb);
+    }
+
+    // cint add/sub/mul through one of the llvm.*.with.overflow intrinsics
+    fn build_checked_int_op(
+        &self,
+        intrinsic: &str,
+        op: u64,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        name: &str,
+    ) -> IntValue<'ctx> {
+        let func = self.module.get_function(intrinsic).unwrap();
+        let result = self
+            .main_builder
+            .build_call(func, &[lhs.into(), rhs.into()], name)
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_struct_value();
+        let value = self
+            .main_builder
+            .build_extract_value(result, 0, name)
+            .unwrap()
+            .into_int_value();
+        let overflowed = self
+            .main_builder
+            .build_extract_value(result, 1, "overflowed")
+            .unwrap()
+            .into_int_value();
+        self.build_cint_check(overflowed, op);
+        value
+    }
+
+    // sdiv has no overflow intrinsic, so check for / 0 and INT_MIN / -1 by hand
+    fn build_checked_int_div(&self, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>) -> IntValue<'ctx> {
+        let i32_type = self.context.i32_type();
+        let div_by_zero = self.main_builder.build_int_compare(
+            IntPredicate::EQ,
+            rhs,
+            i32_type.const_zero(),
+            "div_by_zero",
+        );
+        let lhs_is_min = self.main_builder.build_int_compare(
+            IntPredicate::EQ,
+            lhs,
+            i32_type.const_int(i32::MIN as u64, true),
+            "lhs_is_min",
+        );
+        let rhs_is_neg_one = self.main_builder.build_int_compare(
+            IntPredicate::EQ,
+            rhs,
+            i32_type.const_int(-1i64 as u64, true),
+            "rhs_is_neg_one",
+        );
+        let min_by_neg_one = self
+            .main_builder
+            .build_and(lhs_is_min, rhs_is_neg_one, "min_by_neg_one");
+        let failed = self
+            .main_builder
+            .build_or(div_by_zero, min_by_neg_one, "div_overflowed");
+        self.build_cint_check(failed, CINT_DIV);
+        self.main_builder.build_int_signed_div(lhs, rhs, "div")
+    }
+
+    fn array_trap(&self) -> (BasicBlock<'ctx>, [PhiValue<'ctx>; 4]) {
+        if let Some(trap) = self.array_trap.get() {
+            return trap;
+        }
+        let i32_type = self.context.i32_type();
+        let msg_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let trap_bb = self
+            .context
+            .append_basic_block(self.current_fn_being_compiled.unwrap(), "array_trap");
+        let bldr = self.context.create_builder();
+        bldr.position_at_end(trap_bb);
+        let phis = [
+            bldr.build_phi(i32_type, "index"),
+            bldr.build_phi(i32_type, "len"),
+            bldr.build_phi(msg_type, "msg"),
+            bldr.build_phi(i32_type, "msg_len"),
+        ];
+        let args: Vec<BasicValueEnum> = phis.iter().map(|phi| phi.as_basic_value()).collect();
+        let trap_fn = self.module.get_function("__array_out_of_bounds__").unwrap();
+        bldr.build_call(trap_fn, &args, "call");
+        bldr.build_unreachable();
+        self.array_trap.set(Some((trap_bb, phis)));
+        (trap_bb, phis)
+    }
+
+    // branch to the trap block if index isn't below len, and keep building after the check
+    // otherwise
+    fn build_bounds_check(&self, index: IntValue<'ctx>, len: IntValue<'ctx>, span: Span) {
+        // unsigned, so negative indices are out of bounds too
+        let out_of_bounds =
+            self.main_builder
+                .build_int_compare(IntPredicate::UGE, index, len, "out_of_bounds");
+        let [msg_ptr, msg_len] = self.location_args(span);
+        let (trap_bb, phis) = self.array_trap();
+        let check_bb = self.main_builder.get_insert_block().unwrap();
+        let ok_bb = self.context.insert_basic_block_after(check_bb, "in_bounds");
+        self.main_builder
+            .build_conditional_branch(out_of_bounds, trap_bb, ok_bb);
+        let incoming: [BasicValueEnum; 4] = [index.into(), len.into(), msg_ptr, msg_len];
+        for (phi, value) in phis.iter().zip(incoming.iter()) {
+            phi.add_incoming(&[(value, check_bb)]);
+        }
+        self.main_builder.position_at_end(ok_bb);
+    }
+
+    // arg_no is the 1-based position of vdecl in the function's arguments, if it is one
     fn add_var_spot_to_fn_stack_frame(
         &mut self,
         argtype: BasicTypeEnum<'ctx>,
-        tctype: TCType,
-        varname: String,
+        vdecl: &TCVDecl,
+        arg_no: Option<u32>,
     ) -> Result<PointerValue<'ctx>> {
         let bldr = self.context.create_builder();
+        let varname = vdecl.varid.clone();
 
         let entry = self
             .current_fn_being_compiled
@@ -215,7 +1064,8 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             .get_first_basic_block()
             .unwrap();
 
-        match entry.get_first_instruction() {
+        let first_instr = entry.get_first_instruction();
+        match first_instr {
             Some(first_instr) => bldr.position_before(&first_instr),
             None => bldr.position_at_end(entry),
         }
@@ -226,8 +1076,59 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             BasicTypeEnum::PointerType(pt) => bldr.build_alloca(pt, &varname),
             _ => Err(anyhow!("unsupported argument type to add to stack frame"))?,
         };
+
+        if let Some(di) = &self.debug_info {
+            let scope = *di.scopes.last().expect("variable outside of a function");
+            let file = di.compile_unit.get_file();
+            let (line, col) = di.lines.line_col(vdecl.span.lo);
+            let var_type = self
+                .debug_type(di, vdecl.type_)?
+                .ok_or(anyhow!("variables cannot have void type"))?;
+            let var_info = match arg_no {
+                Some(arg_no) => di.builder.create_parameter_variable(
+                    scope,
+                    &varname,
+                    arg_no,
+                    file,
+                    line,
+                    var_type,
+                    true,
+                    DIFlags::ZERO,
+                ),
+                None => di.builder.create_auto_variable(
+                    scope,
+                    &varname,
+                    file,
+                    line,
+                    var_type,
+                    true,
+                    DIFlags::ZERO,
+                    0,
+                ),
+            };
+            let loc = di
+                .builder
+                .create_debug_location(self.context, line, col, scope, None);
+            // the declare goes right after the alloca it describes
+            match first_instr {
+                Some(first_instr) => {
+                    di.builder.insert_declare_before_instruction(
+                        var_spot,
+                        Some(var_info),
+                        None,
+                        loc,
+                        first_instr,
+                    );
+                }
+                None => {
+                    di.builder
+                        .insert_declare_at_end(var_spot, Some(var_info), None, loc, entry);
+                }
+            }
+        }
+
         self.current_fn_stack_variables
-            .insert(varname, (var_spot, tctype));
+            .insert(varname, (var_spot, vdecl.type_));
         Ok(var_spot)
     }
 
@@ -245,16 +1146,44 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             None => self.context.void_type().fn_type(args.as_slice(), false),
             Some(basictype) => basictype.clone().fn_type(args.as_slice(), false),
         };
-        self.module.add_function(
+        let fn_ = self.module.add_function(
             &extern_.globid.clone(),
             fn_type,
             Some(Linkage::ExternalWeak),
         );
+        self.add_noalias_attributes(fn_, &extern_.args);
         Ok(())
     }
 
-    fn lift_function(&mut self, func: TCFunc) -> Result<()> {
-        //nprintln!("LIFTING FUNCITON: {:?}", func);
+    // globals are private to their file, and named $name so they can't clash with a function
+    fn lift_global(&mut self, global: TCGlobal) {
+        let value = self.lift_literal(&global.value);
+        let name = format!("${}", global.vdecl.varid);
+        let var = self.module.add_global(value.get_type(), None, &name);
+        var.set_initializer(&value);
+        var.set_constant(global.constant);
+        var.set_linkage(Linkage::Private);
+        self.globals.insert(
+            global.vdecl.varid,
+            (var.as_pointer_value(), global.vdecl.type_),
+        );
+    }
+
+    // tell llvm which pointer arguments can't alias anything else in the callee.
+    // the typechecker rejects calls that would break this promise.
+    fn add_noalias_attributes(&self, fn_: FunctionValue<'ctx>, arg_types: &Vec<TCType>) {
+        let noalias_kind = Attribute::get_named_enum_kind_id("noalias");
+        for (i, arg_type) in arg_types.iter().enumerate() {
+            if let TCType::Ref(true, _) = arg_type {
+                let noalias = self.context.create_enum_attribute(noalias_kind, 0);
+                fn_.add_attribute(AttributeLoc::Param(i as u32), noalias);
+            }
+        }
+    }
+
+    // add the function's prototype to the module. every function is declared before any body is
+    // lifted, so calls can go to functions defined later in the file
+    fn declare_function(&self, func: &TCFunc) -> Result<FunctionValue<'ctx>> {
         let ret_type_: Option<BasicTypeEnum> = self.lift_type(func.type_.clone())?;
         let args: Vec<BasicTypeEnum> = func
             .args
@@ -271,24 +1200,52 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
         let fn_ = self
             .module
             .add_function(&func.globid.clone(), fn_type, None);
+        let arg_types = func.args.iter().map(|x| x.type_).collect();
+        self.add_noalias_attributes(fn_, &arg_types);
+        Ok(fn_)
+    }
+
+    fn lift_function(&mut self, func: TCFunc) -> Result<()> {
+        //nprintln!("LIFTING FUNCITON: {:?}", func);
+        let ret_type_: Option<BasicTypeEnum> = self.lift_type(func.type_.clone())?;
+        let args: Vec<BasicTypeEnum> = func
+            .args
+            .iter()
+            .map(|x| {
+                self.lift_type(x.type_.clone())
+                    .and_then(|result| result.ok_or(anyhow!("func args cannot have void type")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // declared by declare_function
+        let fn_ = self.module.get_function(&func.globid).unwrap();
 
         self.current_fn_being_compiled = Some(fn_);
-        self.current_fn_stack_variables = HashMap::new();
+        self.current_fn_stack_variables = self.globals.clone();
+        self.cint_trap.set(None);
+        self.array_trap.set(None);
+        self.array_scopes = vec![vec![]];
         let function_block = self.context.append_basic_block(fn_, "entry");
         self.main_builder.position_at_end(function_block);
+        self.debug_enter_function(fn_, &func)?;
 
         for (i, arg) in fn_.get_param_iter().enumerate() {
-            let arg_name = func.args[i].varid.clone();
-            let arg_type = func.args[i].type_.clone();
-            let alloca = self.add_var_spot_to_fn_stack_frame(args[i], arg_type, arg_name)?;
+            let alloca =
+                self.add_var_spot_to_fn_stack_frame(args[i], &func.args[i], Some(i as u32 + 1))?;
             self.main_builder.build_store(alloca, arg);
+            // array arguments come with a reference of their own
+            if let TCType::Array(_) = func.args[i].type_ {
+                self.array_scopes[0].push(alloca);
+            }
         }
 
         let _last_stmt = &func.blk.stmts.last().clone();
 
         if !self.lift_stmt(&TCStmt::Blk(func.blk))? {
             match ret_type_ {
-                None => self.main_builder.build_return(None),
+                None => {
+                    self.release_arrays(&self.array_scopes);
+                    self.main_builder.build_return(None)
+                }
                 Some(_) => self.main_builder.build_unreachable(),
             };
         };
@@ -318,6 +1275,8 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                  * z; // 4
                  */
                 let parent_block_scope = self.current_fn_stack_variables.clone();
+                self.debug_enter_block(blk.span);
+                self.array_scopes.push(vec![]);
 
                 let mut returns = false;
                 for inner_stmt in blk.stmts.iter() {
@@ -327,6 +1286,11 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                     }
                 }
 
+                let arrays = self.array_scopes.pop().unwrap();
+                if !returns {
+                    self.release_arrays(&[arrays]);
+                }
+                self.debug_exit_block();
                 self.current_fn_stack_variables = parent_block_scope;
                 Ok(returns)
             }
@@ -337,9 +1301,11 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                         // unwrap can't fail because return expression can't be void and the
                         // typechecker confirms this
                         let lifted_ret = self.lift_exp(ret)?.unwrap();
+                        self.release_arrays(&self.array_scopes);
                         self.main_builder.build_return(Some(&lifted_ret));
                     }
                     None => {
+                        self.release_arrays(&self.array_scopes);
                         self.main_builder.build_return(None);
                     }
                 };
@@ -347,11 +1313,7 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
             }
             TCStmt::VDeclStmt { vdecl, exp } => {
                 let lifted_type = self.lift_type(vdecl.type_)?.unwrap();
-                let var = self.add_var_spot_to_fn_stack_frame(
-                    lifted_type,
-                    vdecl.type_,
-                    vdecl.varid.clone(),
-                )?;
+                let var = self.add_var_spot_to_fn_stack_frame(lifted_type, vdecl, None)?;
                 //println!("vdecl time baby: {:?}, {:?}", vdecl, exp);
 
                 if let TCType::Ref(_, _) = vdecl.type_ {
@@ -379,11 +1341,18 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                 } else {
                     let lifted_exp = self.lift_exp(exp)?.unwrap();
                     self.main_builder.build_store(var, lifted_exp);
+                    if let TCType::Array(_) = vdecl.type_ {
+                        self.array_scopes.last_mut().unwrap().push(var);
+                    }
                 };
                 Ok(false)
             }
             TCStmt::ExpStmt(exp) => {
-                self.lift_exp(exp)?;
+                let lifted_exp = self.lift_exp(exp)?;
+                // the reference an array-valued expression gives back isn't kept by anything
+                if let TCType::Array(_) = exp.type_ {
+                    self.release_array(lifted_exp.unwrap());
+                }
                 Ok(false)
             }
             TCStmt::WhileStmt { cond, stmt: body } => {
@@ -400,7 +1369,7 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
 
                 // execute body and check condition again
                 self.main_builder.position_at_end(loop_bb);
-                if !self.lift_stmt(body)? {
+                if !self.lift_scoped_stmt(body)? {
                     let end_cond = self.lift_exp(cond)?.unwrap().into_int_value();
                     self.main_builder
                         .build_conditional_branch(end_cond, loop_bb, post_bb);
@@ -427,7 +1396,7 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                         self.main_builder
                             .build_conditional_branch(lifted_cond, body_bb, post_bb);
                         self.main_builder.position_at_end(body_bb);
-                        if !self.lift_stmt(body)? {
+                        if !self.lift_scoped_stmt(body)? {
                             self.main_builder.build_unconditional_branch(post_bb);
                         }
                         self.main_builder.position_at_end(post_bb);
@@ -438,19 +1407,23 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                         self.main_builder
                             .build_conditional_branch(lifted_cond, body_bb, else_bb);
                         self.main_builder.position_at_end(body_bb);
-                        let if_returns = self.lift_stmt(body)?;
+                        let if_returns = self.lift_scoped_stmt(body)?;
+                        // lifting either branch can start new blocks (nested control flow,
+                        // cint checks), so the jump to endif goes wherever each one ended up
+                        let body_end_bb = self.main_builder.get_insert_block().unwrap();
                         self.main_builder.position_at_end(else_bb);
-                        let else_returns = self.lift_stmt(real_else_stmt_of_atlanta)?;
+                        let else_returns = self.lift_scoped_stmt(real_else_stmt_of_atlanta)?;
+                        let else_end_bb = self.main_builder.get_insert_block().unwrap();
                         if if_returns && else_returns {
                             Ok(true)
                         } else {
                             let post_bb = self.context.append_basic_block(parent, "endif");
                             if !if_returns {
-                                self.main_builder.position_at_end(body_bb);
+                                self.main_builder.position_at_end(body_end_bb);
                                 self.main_builder.build_unconditional_branch(post_bb);
                             }
                             if !else_returns {
-                                self.main_builder.position_at_end(else_bb);
+                                self.main_builder.position_at_end(else_end_bb);
                                 self.main_builder.build_unconditional_branch(post_bb);
                             }
                             self.main_builder.position_at_end(post_bb);
@@ -497,30 +1470,73 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                     .i32_type()
                     .const_int(strlit.len().try_into().unwrap(), false)
                     .into();
-
-                let target_data = self.execution_engine.get_target_data();
-                let strlit_2 = &strlit[1..strlit.len() - 1].to_string();
-                let strlit_box = Box::new(strlit_2.clone());
-                let strlit_box_leaked = Box::leak(strlit_box);
                 let strlit_ptr_arg = self
-                    .context
-                    .ptr_sized_int_type(&target_data, None)
-                    .const_int(strlit_box_leaked as *const String as u64, false)
+                    .main_builder
+                    .build_global_string_ptr(strlit, "slit")
+                    .as_pointer_value()
                     .into();
                 let func = self.module.get_function("__printstr__").unwrap();
-                let _ptrval = strlit_box_leaked as *const String as u64;
 
                 self.main_builder
                     .build_call(func, &[strlit_ptr_arg, strlen_arg], "call");
                 Ok(false)
             }
+            TCStmt::Assert(cond) => {
+                let ok = self.lift_exp(cond)?.unwrap().into_int_value();
+                let msg = self.assert_message(cond.span, self.source_text(cond.span));
+                self.build_assert(ok, &msg, "__assert_failed__", &[]);
+                Ok(false)
+            }
+            TCStmt::AssertEq { lhs, rhs } => {
+                let lhs_val = self.lift_exp(lhs)?.unwrap();
+                let rhs_val = self.lift_exp(rhs)?.unwrap();
+                let (ok, failed_fn) = match (lhs_val, rhs_val) {
+                    (BasicValueEnum::FloatValue(l), BasicValueEnum::FloatValue(r)) => (
+                        self.main_builder
+                            .build_float_compare(FloatPredicate::UEQ, l, r, "eq"),
+                        "__assert_eq_failed_float__",
+                    ),
+                    (BasicValueEnum::IntValue(l), BasicValueEnum::IntValue(r)) => (
+                        self.main_builder
+                            .build_int_compare(IntPredicate::EQ, l, r, "eq"),
+                        match lhs.type_ {
+                            TCType::AtomType(TCAtomType::BoolType) => {
+                                "__assert_eq_failed_bool__"
+                            }
+                            _ => "__assert_eq_failed_int__",
+                        },
+                    ),
+                    _ => Err(anyhow!("assert_eq on values of different types (bug)"))?,
+                };
+                let asserted = format!(
+                    "{} == {}",
+                    self.source_text(lhs.span),
+                    self.source_text(rhs.span)
+                );
+                let msg = self.assert_message(lhs.span, &asserted);
+                self.build_assert(ok, &msg, failed_fn, &[lhs_val, rhs_val]);
+                Ok(false)
+            }
+        }
+    }
+
+    // the body of a while or an if is a scope of its own, even when it isn't a block
+    fn lift_scoped_stmt(&mut self, stmt: &TCStmt) -> Result<bool> {
+        self.array_scopes.push(vec![]);
+        let returns = self.lift_stmt(stmt)?;
+        let arrays = self.array_scopes.pop().unwrap();
+        if !returns {
+            self.release_arrays(&[arrays]);
         }
+        Ok(returns)
     }
 
-    // check the type of an expression and get its value
+    // check the type of an expression and get its value. array values come with a reference
+    // the caller has to keep or release
     fn lift_exp(&self, exp: &TypedExp) -> Result<Option<BasicValueEnum<'ctx>>> {
+        self.set_debug_location(exp.span);
         match exp.type_ {
-            TCType::AtomType(_tca) => self.lift_tcexp(&exp.exp),
+            TCType::AtomType(_) | TCType::Array(_) => self.lift_tcexp(&exp.exp, exp.span),
             TCType::VoidType => self.lift_exp_to_void(&exp.exp),
             _ => Err(anyhow!(
                 "typed expression with reference type (likely a bug)"
@@ -586,7 +1602,7 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
         Ok(None)
     }
 
-    fn lift_tcexp(&self, exp: &TCExp) -> Result<Option<BasicValueEnum<'ctx>>> {
+    fn lift_tcexp(&self, exp: &TCExp, span: Span) -> Result<Option<BasicValueEnum<'ctx>>> {
         let val = match exp {
             TCExp::Assign { varid, exp } => {
                 let ass_val = self.lift_exp(&exp)?.unwrap();
@@ -596,6 +1612,12 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                     let ptr = self.main_builder.build_load(*var, "load");
                     self.main_builder
                         .build_store(ptr.into_pointer_value(), ass_val);
+                } else if let TCType::Array(_) = type_ {
+                    // the variable takes ass_val's reference, and the result needs another
+                    let old = self.main_builder.build_load(*var, "old");
+                    self.main_builder.build_store(*var, ass_val);
+                    self.release_array(old);
+                    self.retain_array(ass_val);
                 } else {
                     self.main_builder.build_store(*var, ass_val);
                 }
@@ -656,64 +1678,41 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                             BOp::Add => BasicValueEnum::IntValue(if !checked_overflow {
                                 self.main_builder.build_int_add(lhs_val, rhs_val, "add")
                             } else {
-                                let func = self.module.get_function("__sadd__").unwrap();
-                                self.main_builder
-                                    .build_call(
-                                        func,
-                                        vec![lifted_lhs, lifted_rhs].as_slice(),
-                                        "call",
-                                    )
-                                    .try_as_basic_value()
-                                    .left()
-                                    .expect("error: failed checked add")
-                                    .into_int_value()
+                                self.build_checked_int_op(
+                                    "llvm.sadd.with.overflow.i32",
+                                    CINT_ADD,
+                                    lhs_val,
+                                    rhs_val,
+                                    "add",
+                                )
                             }),
                             BOp::Sub => BasicValueEnum::IntValue(if !checked_overflow {
                                 self.main_builder.build_int_sub(lhs_val, rhs_val, "sub")
                             } else {
-                                let func = self.module.get_function("__ssub__").unwrap();
-                                self.main_builder
-                                    .build_call(
-                                        func,
-                                        vec![lifted_lhs, lifted_rhs].as_slice(),
-                                        "call",
-                                    )
-                                    .try_as_basic_value()
-                                    .left()
-                                    .expect("error: failed checked sub")
-                                    .into_int_value()
+                                self.build_checked_int_op(
+                                    "llvm.ssub.with.overflow.i32",
+                                    CINT_SUB,
+                                    lhs_val,
+                                    rhs_val,
+                                    "sub",
+                                )
                             }),
                             BOp::Mult => BasicValueEnum::IntValue(if !checked_overflow {
                                 self.main_builder.build_int_mul(lhs_val, rhs_val, "mul")
                             } else {
-                                let func = self.module.get_function("__smul__").unwrap();
-                                self.main_builder
-                                    .build_call(
-                                        func,
-                                        vec![lifted_lhs, lifted_rhs].as_slice(),
-                                        "call",
-                                    )
-                                    .try_as_basic_value()
-                                    .left()
-                                    .expect("error: failed checked mul")
-                                    .into_int_value()
+                                self.build_checked_int_op(
+                                    "llvm.smul.with.overflow.i32",
+                                    CINT_MUL,
+                                    lhs_val,
+                                    rhs_val,
+                                    "mul",
+                                )
                             }),
-                            BOp::Div => BasicValueEnum::IntValue(if !checked_overflow{
+                            BOp::Div => BasicValueEnum::IntValue(if !checked_overflow {
                                 self.main_builder.build_int_signed_div(lhs_val, rhs_val, "div")
                             } else {
-                                let func = self.module.get_function("__sdiv__").unwrap();
-                                self.main_builder
-                                    .build_call(
-                                        func,
-                                        vec![lifted_lhs, lifted_rhs].as_slice(),
-                                        "call",
-                                    )
-                                    .try_as_basic_value()
-                                    .left()
-                                    .expect("error: failed checked div")
-                                    .into_int_value()
-                            }
-                            ),
+                                self.build_checked_int_div(lhs_val, rhs_val)
+                            }),
                             BOp::EqTo => {
                                 BasicValueEnum::IntValue(self.main_builder.build_int_compare(
                                     IntPredicate::EQ,
@@ -805,20 +1804,13 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                                     self.main_builder.build_int_neg(val, "neg"),
                                 )
                             } else {
-                                let func = self.module.get_function("__ssub__").unwrap();
-                                self.main_builder
-                                    .build_call(
-                                        func,
-                                        vec![
-                                            self.context.i32_type().const_int(0, false).into(),
-                                            lifted_exp,
-                                        ]
-                                        .as_slice(),
-                                        "call",
-                                    )
-                                    .try_as_basic_value()
-                                    .left()
-                                    .expect("error: failed checked signed negation")
+                                BasicValueEnum::IntValue(self.build_checked_int_op(
+                                    "llvm.ssub.with.overflow.i32",
+                                    CINT_SUB,
+                                    self.context.i32_type().const_zero(),
+                                    val,
+                                    "neg",
+                                ))
                             }
                         }
                         UOp::BitwiseNeg => {
@@ -834,17 +1826,7 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                     _ => Err(anyhow!("invalid value in unary expression"))?,
                 }
             }
-            TCExp::Literal(lit) => match lit {
-                Lit::LitInt(i) => {
-                    BasicValueEnum::IntValue(self.context.i32_type().const_int(*i as u64, true))
-                }
-                Lit::LitFloat(i) => {
-                    BasicValueEnum::FloatValue(self.context.f64_type().const_float(*i))
-                }
-                Lit::LitBool(i) => {
-                    BasicValueEnum::IntValue(self.context.bool_type().const_int(*i as u64, true))
-                }
-            },
+            TCExp::Literal(lit) => self.lift_literal(lit),
             TCExp::VarVal(varid) => {
                 // typechecker makes sure variable is in scope here
                 let var = self.current_fn_stack_variables.get(varid.as_str()).unwrap();
@@ -855,9 +1837,50 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
                             .build_load(loc1.into_pointer_value(), varid.as_str())
                     }
                     TCType::AtomType(_) => self.main_builder.build_load(var.0, varid.as_str()),
+                    TCType::Array(_) => {
+                        let array = self.main_builder.build_load(var.0, varid.as_str());
+                        self.retain_array(array);
+                        array
+                    }
                     _ => Err(anyhow!("void variable spooooky ooooo!!!"))?,
                 }
             }
+            TCExp::NewArray { type_, len } => {
+                let lifted_len = self.lift_exp(len)?.unwrap();
+                let elem_size = self
+                    .context
+                    .i32_type()
+                    .const_int(self.array_elem_size(*type_), false)
+                    .into();
+                let [msg_ptr, msg_len] = self.location_args(span);
+                self.build_array_call("__array_new__", &[lifted_len, elem_size, msg_ptr, msg_len])
+                    .unwrap()
+            }
+            TCExp::Len(array) => match &array.exp {
+                // borrowed from the variable, like in lift_array_elem
+                TCExp::VarVal(varid) => {
+                    let (var, _) = self.current_fn_stack_variables.get(varid).unwrap();
+                    let lifted_array = self.main_builder.build_load(*var, varid);
+                    let len = self.build_array_len(lifted_array.into_pointer_value());
+                    len.into()
+                }
+                _ => {
+                    let lifted_array = self.lift_exp(array)?.unwrap();
+                    let len = self.build_array_len(lifted_array.into_pointer_value());
+                    self.release_array(lifted_array);
+                    len.into()
+                }
+            },
+            TCExp::Index { varid, index } => {
+                let elem = self.lift_array_elem(varid, index, span)?;
+                self.main_builder.build_load(elem, "elem")
+            }
+            TCExp::AssignIndex { varid, index, exp } => {
+                let ass_val = self.lift_exp(exp)?.unwrap();
+                let elem = self.lift_array_elem(varid, index, span)?;
+                self.main_builder.build_store(elem, ass_val);
+                ass_val
+            }
             TCExp::FuncCall {
                 globid,
                 exps,
@@ -913,19 +1936,79 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
         Ok(Some(val))
     }
 
+    // pointer to $varid[index], after the bounds check
+    fn lift_array_elem(
+        &self,
+        varid: &str,
+        index: &TypedExp,
+        span: Span,
+    ) -> Result<PointerValue<'ctx>> {
+        let lifted_index = self.lift_exp(index)?.unwrap().into_int_value();
+        // typechecker makes sure the variable is an array in scope
+        let (var, type_) = self.current_fn_stack_variables.get(varid).unwrap();
+        let elem_type = match type_ {
+            TCType::Array(elem_type) => self.lift_atom_type(*elem_type)?,
+            _ => Err(anyhow!("indexed a variable that isn't an array (bug)"))?,
+        };
+        // borrowed from the variable, which keeps it alive
+        let array = self
+            .main_builder
+            .build_load(*var, varid)
+            .into_pointer_value();
+        let len = self.build_array_len(array);
+        self.build_bounds_check(lifted_index, len, span);
+        let data = self.array_field(array, ARRAY_DATA_OFFSET, elem_type, "data");
+        // in bounds, so zero-extending is the same as sign-extending
+        let offset =
+            self.main_builder
+                .build_int_z_extend(lifted_index, self.context.i64_type(), "index");
+        Ok(unsafe {
+            self.main_builder
+                .build_in_bounds_gep(data, &[offset], "elem_ptr")
+        })
+    }
+
+    fn lift_literal(&self, lit: &Lit) -> BasicValueEnum<'ctx> {
+        match lit {
+            Lit::LitInt(i) => {
+                BasicValueEnum::IntValue(self.context.i32_type().const_int(*i as u64, true))
+            }
+            Lit::LitFloat(i) => BasicValueEnum::FloatValue(self.context.f64_type().const_float(*i)),
+            Lit::LitBool(i) => {
+                BasicValueEnum::IntValue(self.context.bool_type().const_int(*i as u64, true))
+            }
+        }
+    }
+
     fn lift_type(&self, type_: TCType) -> Result<Option<BasicTypeEnum<'ctx>>> {
         Ok(match type_ {
             TCType::AtomType(type_) => Some(self.lift_atom_type(type_)?),
             TCType::VoidType => None,
-            // TODO: WHAT IS HAPPENING WITH NOALIAS????
+            // noalias is a property of the argument, not the type. see add_noalias_attributes
             TCType::Ref(_noalias, type_) => Some(
                 self.lift_atom_type(type_)?
                     .ptr_type(AddressSpace::Generic)
                     .into(),
             ),
+            // the header of the array on the heap, see ArrayHeader
+            TCType::Array(_) => Some(
+                self.context
+                    .i8_type()
+                    .ptr_type(AddressSpace::Generic)
+                    .into(),
+            ),
         })
     }
 
+    // bytes taken by each element of an array, as lift_atom_type stores them
+    fn array_elem_size(&self, type_: TCAtomType) -> u64 {
+        match type_ {
+            TCAtomType::IntType | TCAtomType::CIntType => 4,
+            TCAtomType::FloatType => 8,
+            TCAtomType::BoolType => 1,
+        }
+    }
+
     fn lift_atom_type(&self, type_: TCAtomType) -> Result<BasicTypeEnum<'ctx>> {
         Ok(match type_ {
             TCAtomType::IntType | TCAtomType::CIntType => self.context.i32_type().into(),
@@ -937,84 +2020,278 @@ impl<'ast: 'ctx, 'ctx> JitDoer<'ctx> {
 
 type KaleidoRunFunc = unsafe extern "C" fn() -> i32;
 
-fn jit_compile_kaleido_prog<'a>(
-    ctxt: &'a Context,
-    toplvl_filename: &'a str,
-    ast: TCProg,
-    opt: bool,
-    oflags: OFlags,
-    time: bool,
-) -> Result<JitFunction<'a, KaleidoRunFunc>> {
-    //https://thedan64.github.io/inkwell/inkwell/enum.OptimizationLevel.html
-    let mut jit_doer = JitDoer::init(ctxt, toplvl_filename, OptimizationLevel::None)?;
-    for e in ast.externs {
-        jit_doer.lift_extern(e)?;
-    }
-    for f in ast.funcs {
-        jit_doer.lift_function(f)?;
+// `--entry name`: the function to call instead of run, and its arguments from the command line
+pub struct Entry {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+// what gets jitted and called when there's an entry, see JitDoer::build_entry_wrapper
+const ENTRY_WRAPPER: &str = "__entry__";
+
+type KaleidoTestFunc = unsafe extern "C" fn();
+
+// `ekcc test`: jit the program and run every `test "name" { ... }` in the input file, each in
+// a process of its own (see testing::run_tests). true if they all passed
+pub fn test(modules: Vec<TCModule>, host: &HostFns, options: &CompileOptions) -> Result<bool> {
+    check_host_externs(&modules, host)?;
+    let tests: Vec<(String, String)> = modules
+        .last()
+        .unwrap()
+        .prog
+        .funcs
+        .iter()
+        .filter_map(|func| func.test.clone().map(|name| (name, func.globid.clone())))
+        .collect();
+    let ctxt = Context::create();
+    let jit_doer = lift_prog(&ctxt, modules, options)?;
+    optimize(&jit_doer.module, options);
+    resolve_externs(
+        &ctxt,
+        &jit_doer.module,
+        &jit_doer.execution_engine,
+        host,
+        &options.libs,
+    )?;
+
+    let mut test_fns = vec![];
+    for (name, globid) in tests {
+        let func: JitFunction<KaleidoTestFunc> =
+            unsafe { jit_doer.execution_engine.get_function(&globid)? };
+        test_fns.push((name, move || {
+            unsafe { func.call() };
+            // every array the test made should have been released by the time it returns
+            if live_arrays() != 0 {
+                println!("error: test leaked {} arrays", live_arrays());
+                std::process::exit(1);
+            }
+        }));
     }
+    Ok(testing::run_tests(&test_fns))
+}
 
+// the entry function's return type, and its arguments parsed as its parameter types
+fn entry_args(modules: &[TCModule], entry: &Entry) -> Result<(TCType, Vec<Value>)> {
+    let func = modules
+        .iter()
+        .flat_map(|module| module.prog.funcs.iter())
+        .find(|func| func.globid == entry.name)
+        .ok_or(anyhow!("no function named {}", entry.name))?;
+    let is_ref = |vdecl: &TCVDecl| match vdecl.type_ {
+        TCType::Ref(_, _) => true,
+        _ => false,
+    };
+    if func.args.iter().any(is_ref) {
+        return Err(anyhow!(
+            "{} takes ref arguments, so it can't be called with --entry",
+            entry.name
+        ));
+    }
+    let is_array = |type_: TCType| matches!(type_, TCType::Array(_));
+    if is_array(func.type_) || func.args.iter().any(|vdecl| is_array(vdecl.type_)) {
+        return Err(anyhow!(
+            "{} takes or returns arrays, so it can't be called with --entry",
+            entry.name
+        ));
+    }
+    if func.args.len() != entry.args.len() {
+        return Err(anyhow!(
+            "{} takes {} arguments, but {} were given",
+            entry.name,
+            func.args.len(),
+            entry.args.len()
+        ));
+    }
+    let args = func
+        .args
+        .iter()
+        .zip(entry.args.iter())
+        .map(|(vdecl, arg)| {
+            Value::parse(vdecl.type_, arg).ok_or(anyhow!(
+                "{} of {} is {}, but got {}",
+                vdecl.varid,
+                entry.name,
+                vdecl.type_,
+                arg
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((func.type_, args))
+}
 
-    if opt {
-        optimize(&jit_doer.module);
-    } else if oflags != OFlags::default() {
-        let sw = Stopwatch::start_new();
+// a typechecked source file, see modules::load
+type TCModule = modules::Module<TCProg>;
+
+// everything from the command line that affects how a program is compiled
+pub struct CompileOptions {
+    // standard pipeline selected by -O
+    pub opt_level: OptLevel,
+    // passes selected by -f or --passes, run after the standard pipeline
+    pub pipeline: Pipeline,
+    pub time: bool,
+    // per-pass report requested by --time-passes
+    pub time_passes: Option<TimingFormat>,
+    // -g, emit debug info for every file
+    pub debug: bool,
+    // libraries from --link-lib and -l, searched for externs when jitting
+    pub libs: Vec<SharedLib>,
+    // --entry, what to call instead of run when jitting
+    pub entry: Option<Entry>,
+}
 
-        pipeline(&jit_doer.module, oflags);
+// lift every file of the program into a fresh module. modules is in the order modules::load
+// gives them, so the input file is last
+fn lift_prog<'a>(
+    ctxt: &'a Context,
+    mut modules: Vec<TCModule>,
+    options: &CompileOptions,
+) -> Result<JitDoer<'a>> {
+    let main = modules.pop().unwrap();
+    //https://thedan64.github.io/inkwell/inkwell/enum.OptimizationLevel.html
+    // with -g, mcjit hands each object it loads to llvm's gdb registration listener, so gdb can
+    // find the dwarf for jitted code through its jit interface. no extra work needed here.
+    let mut jit_doer = JitDoer::init(
+        ctxt,
+        &main.filename,
+        &main.src,
+        OptimizationLevel::None,
+        options.debug,
+    )?;
+    // the main file goes first so its extern declarations are there for the imported
+    // definitions to be linked over
+    jit_doer.lift_items(main.prog)?;
+    for import in modules {
+        jit_doer.lift_import(import, options.debug)?;
+    }
+    Ok(jit_doer)
+}
 
-        let pipeline_ms = sw.elapsed().as_nanos();
-        if time {
-            println!("{}", pipeline_ms);
+fn jit_compile_kaleido_prog<'a>(
+    ctxt: &'a Context,
+    modules: Vec<TCModule>,
+    host: &HostFns,
+    options: &CompileOptions,
+) -> Result<JitFunction<'a, KaleidoRunFunc>> {
+    let entry = match &options.entry {
+        Some(entry) => Some((entry, entry_args(&modules, entry)?)),
+        None => None,
+    };
+    let jit_doer = lift_prog(ctxt, modules, options)?;
+    let run_name = match entry {
+        Some((entry, (ret, args))) => {
+            jit_doer.build_entry_wrapper(&entry.name, ret, &args);
+            ENTRY_WRAPPER
         }
-    } else if time {
-        println!("0");
+        None => "run",
+    };
+
+    let sw = Stopwatch::start_new();
+    optimize(&jit_doer.module, options);
+    let pipeline_ms = sw.elapsed().as_nanos();
+    if options.time {
+        println!("{}", pipeline_ms);
     }
 
+    resolve_externs(
+        ctxt,
+        &jit_doer.module,
+        &jit_doer.execution_engine,
+        host,
+        &options.libs,
+    )?;
     // pull out jitted run function and OFF we go!!
-    let efn = unsafe { jit_doer.execution_engine.get_function("run")? };
+    let efn = unsafe { jit_doer.execution_engine.get_function(run_name)? };
 
     Ok(efn)
 }
 
-pub fn jit(input_filename: &str, ast: TCProg, args: Vec<String>, opt: bool, oflags: OFlags, time: bool) -> Result<i32> {
+// every extern the program declares with the name of a host function has to match it
+fn check_host_externs(modules: &[TCModule], host: &HostFns) -> Result<()> {
+    for module in modules {
+        host.check_externs(&module.prog.externs)
+            .map_err(|msg| anyhow!("{}: {}", module.filename, msg))?;
+    }
+    Ok(())
+}
+
+pub fn jit(modules: Vec<TCModule>, host: &HostFns, options: &CompileOptions) -> Result<i32> {
+    check_host_externs(&modules, host)?;
     let ctxt = Context::create();
-    unsafe { CMD_LINE_ARGS = args };
-    let func = jit_compile_kaleido_prog(&ctxt, input_filename, ast, opt, oflags, time)?;
+    let func = jit_compile_kaleido_prog(&ctxt, modules, host, options)?;
     let sw = Stopwatch::start_new();
 
     let rc = unsafe { func.call() };
 
     let run_ms = sw.elapsed().as_nanos();
-    if time {
+    if options.time {
         println!("{}", run_ms);
     }
 
     Ok(rc)
 }
 
+// pipeline and run times in ns for one `ekcc bench` configuration, warmups left out
+pub struct BenchSamples {
+    pub compile: Vec<u128>,
+    pub run: Vec<u128>,
+}
+
+// compile and run the program warmup + iterations times in this process. passes rewrite the
+// module in place and run can change the globals, so every iteration lifts, optimizes and
+// jits the program afresh and runs that once
+pub fn bench(
+    modules: &[TCModule],
+    host: &HostFns,
+    options: &CompileOptions,
+    warmup: u32,
+    iterations: u32,
+) -> Result<BenchSamples> {
+    if iterations == 0 {
+        return Err(anyhow!("need at least one measured iteration"));
+    }
+    check_host_externs(modules, host)?;
+    let ctxt = Context::create();
+    let mut samples = BenchSamples {
+        compile: vec![],
+        run: vec![],
+    };
+
+    for i in 0..warmup + iterations {
+        let jit_doer = lift_prog(&ctxt, modules.to_vec(), options)?;
+        let sw = Stopwatch::start_new();
+        optimize(&jit_doer.module, options);
+        let pipeline_ns = sw.elapsed().as_nanos();
+        resolve_externs(
+            &ctxt,
+            &jit_doer.module,
+            &jit_doer.execution_engine,
+            host,
+            &options.libs,
+        )?;
+        // machine code is generated here, so the timed call below is only the run
+        let func: JitFunction<KaleidoRunFunc> =
+            unsafe { jit_doer.execution_engine.get_function("run")? };
+        let sw = Stopwatch::start_new();
+        unsafe { func.call() };
+        let run_ns = sw.elapsed().as_nanos();
+        if i >= warmup {
+            samples.compile.push(pipeline_ns);
+            samples.run.push(run_ns);
+        }
+    }
+
+    Ok(samples)
+}
+
 pub fn emit_llvm(
-    input_filename: &str,
+    modules: Vec<TCModule>,
     output_filename: &str,
-    ast: TCProg,
-    opt: bool,
-    oflags: OFlags,
+    options: &CompileOptions,
 ) -> Result<()> {
     let ctxt = Context::create();
-    let mut jit_doer = JitDoer::init(&ctxt, input_filename, OptimizationLevel::None)?;
-    for e in ast.externs {
-        // what do i do with this???
-        let _ext = jit_doer.lift_extern(e)?;
-    }
-    for f in ast.funcs {
-        // what do i do with this???
-        let _fn = jit_doer.lift_function(f)?;
-    }
+    let jit_doer = lift_prog(&ctxt, modules, options)?;
 
-    if opt {
-        optimize(&jit_doer.module);
-    } else if oflags != OFlags::default() {
-        pipeline(&jit_doer.module, oflags);
-    }
+    optimize(&jit_doer.module, options);
 
     match jit_doer.module.print_to_file(Path::new(output_filename)) {
         Ok(_) => Ok(()),
@@ -1025,11 +2302,94 @@ pub fn emit_llvm(
     }
 }
 
-// run the optimization pipeline for the given module
-fn optimize(module: &Module) {
-    run_default_pipeline(module);
+// like emit_llvm, but writes bitcode that `ekcc link` can read back
+pub fn emit_bitcode(
+    modules: Vec<TCModule>,
+    output_filename: &str,
+    options: &CompileOptions,
+) -> Result<()> {
+    let ctxt = Context::create();
+    let jit_doer = lift_prog(&ctxt, modules, options)?;
+
+    optimize(&jit_doer.module, options);
+
+    if jit_doer
+        .module
+        .write_bitcode_to_path(Path::new(output_filename))
+    {
+        Ok(())
+    } else {
+        Err(anyhow!("couldn't write bitcode to {}", output_filename))
+    }
+}
+
+// read back bitcode files written by --emit-bc and link them into one module. an extern in one
+// file resolves to a def of the same name in another; externs nothing defines are left for
+// the host to provide when the program is jitted
+fn link_bitcode<'a>(ctxt: &'a Context, input_filenames: &[&str]) -> Result<Module<'a>> {
+    let read = |filename: &str| {
+        Module::parse_bitcode_from_path(filename, ctxt)
+            .map_err(|msg| anyhow!("could not read bitcode from {}: {}", filename, msg))
+    };
+    let (first, rest) = input_filenames
+        .split_first()
+        .ok_or(anyhow!("no bitcode files to link"))?;
+    let linked = read(first)?;
+    for filename in rest {
+        linked
+            .link_in_module(read(filename)?)
+            .map_err(|msg| anyhow!("could not link {}: {}", filename, msg))?;
+    }
+    Ok(linked)
 }
 
-fn pipeline(module: &Module, oflags: OFlags) {
-    run_pipeline(module, oflags);
+// `ekcc link`: write the linked program to output_filename, as bitcode or as llvm ir
+pub fn link(input_filenames: &[&str], output_filename: &str, emit_llvm: bool) -> Result<()> {
+    let ctxt = Context::create();
+    let module = link_bitcode(&ctxt, input_filenames)?;
+    if emit_llvm {
+        module
+            .print_to_file(Path::new(output_filename))
+            .map_err(|msg| anyhow!("couldn't print module: {}", msg))
+    } else if module.write_bitcode_to_path(Path::new(output_filename)) {
+        Ok(())
+    } else {
+        Err(anyhow!("couldn't write bitcode to {}", output_filename))
+    }
+}
+
+// `ekcc link --jit`: link the bitcode files and run the result
+pub fn jit_linked(
+    input_filenames: &[&str],
+    host: &HostFns,
+    libs: &[SharedLib],
+) -> Result<i32> {
+    let ctxt = Context::create();
+    let module = link_bitcode(&ctxt, input_filenames)?;
+    if module.get_function("run").is_none() {
+        return Err(anyhow!("no function named run in the linked program"));
+    }
+    let execution_engine = module
+        .create_jit_execution_engine(OptimizationLevel::None)
+        .map_err(|msg| anyhow!("cannot create jit execution engine: {}", msg))?;
+    resolve_externs(&ctxt, &module, &execution_engine, host, libs)?;
+    let func: JitFunction<KaleidoRunFunc> = unsafe { execution_engine.get_function("run")? };
+    Ok(unsafe { func.call() })
+}
+
+// run every optimization requested in options on the given module.
+// the --time-passes report goes to stderr so it doesn't mix with program output
+fn optimize(module: &Module, options: &CompileOptions) {
+    match options.time_passes {
+        Some(format) => {
+            let mut timings = vec![];
+            run_default_pipeline(module, options.opt_level, Some(&mut timings));
+            run_pipeline(module, &options.pipeline, Some(&mut timings));
+            eprint!("{}", format_timings(&timings, format));
+        }
+        None => {
+            run_default_pipeline(module, options.opt_level, None);
+            run_pipeline(module, &options.pipeline, None);
+        }
+    }
 }
@@ -0,0 +1,349 @@
+use crate::ast::{Diagnostic, Span};
+
+// turns source text into tokens for the parser. comments are kept as tokens too, for tools
+// that want them, and parse.rs drops them before parsing. a lexical error is recorded and
+// skipped over, so the rest of the file still gets lexed (and parsed)
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    // keywords
+    Import,
+    Extern,
+    Pub,
+    Global,
+    Const,
+    Def,
+    Test,
+    Return,
+    While,
+    If,
+    Else,
+    Print,
+    Assert,
+    AssertEq,
+    True,
+    False,
+    Int,
+    CInt,
+    Float,
+    Bool,
+    Void,
+    Ref,
+    NoAlias,
+    New,
+    Len,
+    // punctuation
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Semi,
+    Comma,
+    Dollar,
+    ColonColon,
+    Assign,
+    EqEq,
+    Lt,
+    Gt,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Bang,
+    AndAnd,
+    OrOr,
+    Ident(String),
+    // too big for a u64 saturates, the parser reports anything too big for an int
+    IntLit(u64),
+    FloatLit(f64),
+    // with the quotes taken off and the escapes replaced
+    StrLit(String),
+    // from the # to the end of the line, without the newline
+    Comment(String),
+}
+
+impl Tok {
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Tok::Comment(_))
+    }
+}
+
+pub type Spanned = (usize, Tok, usize);
+
+const ESCAPES: &str = r#"\n, \t, \" and \\"#;
+
+// every token in src with where it starts and ends, and the lexical errors
+pub fn tokens(src: &str) -> (Vec<Spanned>, Vec<Diagnostic>) {
+    let mut lexer = Lexer {
+        src,
+        pos: 0,
+        errors: vec![],
+    };
+    let mut tokens = vec![];
+    while let Some(c) = lexer.peek() {
+        let lo = lexer.pos;
+        let tok = match c {
+            c if c.is_whitespace() => {
+                lexer.bump();
+                continue;
+            }
+            '#' => Tok::Comment(lexer.eat_while(|c| c != '\n' && c != '\r').to_string()),
+            '"' => lexer.string(),
+            '0'..='9' => lexer.number(),
+            '.' if lexer.peek_second().is_some_and(|c| c.is_ascii_digit()) => lexer.number(),
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let word = lexer.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                keyword(word).unwrap_or_else(|| Tok::Ident(word.to_string()))
+            }
+            _ => match lexer.punctuation() {
+                Some(tok) => tok,
+                None => continue,
+            },
+        };
+        tokens.push((lo, tok, lexer.pos));
+    }
+    (tokens, lexer.errors)
+}
+
+fn keyword(word: &str) -> Option<Tok> {
+    Some(match word {
+        "import" => Tok::Import,
+        "extern" => Tok::Extern,
+        "pub" => Tok::Pub,
+        "global" => Tok::Global,
+        "const" => Tok::Const,
+        "def" => Tok::Def,
+        "test" => Tok::Test,
+        "return" => Tok::Return,
+        "while" => Tok::While,
+        "if" => Tok::If,
+        "else" => Tok::Else,
+        "print" => Tok::Print,
+        "assert" => Tok::Assert,
+        "assert_eq" => Tok::AssertEq,
+        "true" => Tok::True,
+        "false" => Tok::False,
+        "int" => Tok::Int,
+        "cint" => Tok::CInt,
+        "float" => Tok::Float,
+        "bool" => Tok::Bool,
+        "void" => Tok::Void,
+        "ref" => Tok::Ref,
+        "noalias" => Tok::NoAlias,
+        "new" => Tok::New,
+        "len" => Tok::Len,
+        _ => return None,
+    })
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.src[self.pos..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat_while<F: Fn(char) -> bool>(&mut self, f: F) -> &'a str {
+        let lo = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+        &self.src[lo..self.pos]
+    }
+
+    fn error<S: Into<String>>(&mut self, lo: usize, hi: usize, msg: S) {
+        self.errors.push(Diagnostic::new(Span::new(lo, hi), msg));
+    }
+
+    fn punctuation(&mut self) -> Option<Tok> {
+        let lo = self.pos;
+        let c = self.bump()?;
+        let second = self.peek();
+        let (tok, two) = match (c, second) {
+            (':', Some(':')) => (Tok::ColonColon, true),
+            ('=', Some('=')) => (Tok::EqEq, true),
+            ('&', Some('&')) => (Tok::AndAnd, true),
+            ('|', Some('|')) => (Tok::OrOr, true),
+            ('(', _) => (Tok::LParen, false),
+            (')', _) => (Tok::RParen, false),
+            ('{', _) => (Tok::LBrace, false),
+            ('}', _) => (Tok::RBrace, false),
+            ('[', _) => (Tok::LBracket, false),
+            (']', _) => (Tok::RBracket, false),
+            (';', _) => (Tok::Semi, false),
+            (',', _) => (Tok::Comma, false),
+            ('$', _) => (Tok::Dollar, false),
+            ('=', _) => (Tok::Assign, false),
+            ('<', _) => (Tok::Lt, false),
+            ('>', _) => (Tok::Gt, false),
+            ('+', _) => (Tok::Plus, false),
+            ('-', _) => (Tok::Minus, false),
+            ('*', _) => (Tok::Star, false),
+            ('/', _) => (Tok::Slash, false),
+            ('!', _) => (Tok::Bang, false),
+            (':', _) => return self.unexpected(lo, c, " (module names are followed by `::`)"),
+            ('&', _) => return self.unexpected(lo, c, " (`&&` is and)"),
+            ('|', _) => return self.unexpected(lo, c, " (`||` is or)"),
+            ('\'', _) => return self.unexpected(lo, c, " (strings are in double quotes)"),
+            _ => return self.unexpected(lo, c, ""),
+        };
+        if two {
+            self.bump();
+        }
+        Some(tok)
+    }
+
+    fn unexpected(&mut self, lo: usize, c: char, hint: &str) -> Option<Tok> {
+        self.error(lo, self.pos, format!("unexpected character `{}`{}", c, hint));
+        None
+    }
+
+    // a string literal, up to the closing quote or the end of the line
+    fn string(&mut self) -> Tok {
+        let lo = self.pos;
+        self.bump();
+        let mut contents = String::new();
+        loop {
+            let escape_lo = self.pos;
+            match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    break;
+                }
+                None | Some('\n') | Some('\r') => {
+                    self.error(lo, self.pos, "unterminated string, it needs a closing `\"`");
+                    break;
+                }
+                Some('\\') => {
+                    self.bump();
+                    match self.peek() {
+                        Some('n') => contents.push('\n'),
+                        Some('t') => contents.push('\t'),
+                        Some('"') => contents.push('"'),
+                        Some('\\') => contents.push('\\'),
+                        // the unterminated string is reported next time round
+                        None | Some('\n') | Some('\r') => continue,
+                        Some(c) => {
+                            let hi = self.pos + c.len_utf8();
+                            let msg =
+                                format!("unknown escape `\\{}`, the escapes are {}", c, ESCAPES);
+                            self.error(escape_lo, hi, msg);
+                            contents.push(c);
+                        }
+                    }
+                    self.bump();
+                }
+                Some(c) => {
+                    contents.push(c);
+                    self.bump();
+                }
+            }
+        }
+        Tok::StrLit(contents)
+    }
+
+    // 123, 1_000, 0xff, 0b1010, 1.5, .5, 5., 1e10 or 1.5e-3
+    fn number(&mut self) -> Tok {
+        let lo = self.pos;
+        let radix = match (self.peek(), self.peek_second()) {
+            (Some('0'), Some('x')) | (Some('0'), Some('X')) => 16,
+            (Some('0'), Some('b')) | (Some('0'), Some('B')) => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.bump();
+            self.bump();
+        }
+        let digits = self.digits(radix);
+        let mut tok = if radix != 10 && digits.is_empty() {
+            self.error(lo, self.pos, "number has no digits after its prefix");
+            Tok::IntLit(0)
+        } else {
+            Tok::IntLit(digits.chars().fold(0u64, |n, c| {
+                n.saturating_mul(radix as u64)
+                    .saturating_add(c.to_digit(radix).unwrap() as u64)
+            }))
+        };
+
+        // a decimal number with a point or an exponent is a float: 1.5, .5, 5., 1e10, 1.5e-3
+        let mut float = None;
+        if radix == 10 && self.peek() == Some('.') {
+            self.bump();
+            float = Some(format!("{}.{}", digits, self.digits(10)));
+        }
+        // 0x1.5 would otherwise be 0x1 and then .5
+        if radix != 10
+            && self.peek() == Some('.')
+            && self.peek_second().is_some_and(|c| c.is_ascii_alphanumeric())
+        {
+            let point_lo = self.pos;
+            self.bump();
+            self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            let msg = "hex/binary literals can't have a fractional part";
+            self.error(point_lo, point_lo + 1, msg);
+        }
+        if let (10, Some('e')) | (10, Some('E')) = (radix, self.peek()) {
+            let mantissa = float.unwrap_or_else(|| digits.clone());
+            let exponent_lo = self.pos;
+            self.bump();
+            let sign = match self.peek() {
+                Some(c) if c == '+' || c == '-' => {
+                    self.bump();
+                    c.to_string()
+                }
+                _ => String::new(),
+            };
+            let exponent = self.digits(10);
+            if exponent.is_empty() {
+                self.error(exponent_lo, self.pos, "exponent has no digits");
+                float = Some(mantissa);
+            } else {
+                float = Some(format!("{}e{}{}", mantissa, sign, exponent));
+            }
+        }
+        if let Some(float) = float {
+            // too big is inf and too small is 0, which the parser reports
+            tok = Tok::FloatLit(float.parse().unwrap_or(0.0));
+        }
+
+        // 12abc or 0b102 is one bad number rather than a number and then something else
+        let suffix_lo = self.pos;
+        let suffix = self.eat_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !suffix.is_empty() {
+            let msg = match suffix.chars().next() {
+                Some(c) if c.is_ascii_digit() || (radix == 16 && c.is_ascii_hexdigit()) => {
+                    format!("invalid digit `{}` in a base {} number", c, radix)
+                }
+                _ => format!("invalid suffix `{}` on a number", suffix),
+            };
+            self.error(suffix_lo, self.pos, msg);
+        }
+        tok
+    }
+
+    // digits in radix, with the _s between them dropped
+    fn digits(&mut self, radix: u32) -> String {
+        let lo = self.pos;
+        let text = self.eat_while(|c| c.is_digit(radix) || c == '_');
+        if text.starts_with('_') || text.ends_with('_') || text.contains("__") {
+            self.error(lo, self.pos, "`_` in a number has to be between two digits");
+        }
+        text.chars().filter(|c| *c != '_').collect()
+    }
+}
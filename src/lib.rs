@@ -0,0 +1,18 @@
+#[macro_use]
+extern crate lalrpop_util;
+pub mod ast;
+pub mod bench;
+pub mod dylib;
+pub mod fold;
+pub mod host;
+pub mod jit;
+pub mod lexer;
+pub mod lint;
+pub mod lsp;
+pub mod modules;
+pub mod optimize;
+pub mod parse;
+pub mod testing;
+pub mod typecheck;
+
+lalrpop_mod!(pub kaleidoscope); // synthesized by LALRPOP
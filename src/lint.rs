@@ -0,0 +1,548 @@
+use crate::ast::{Diagnostic, Span};
+use crate::typecheck::{TCExp, TCFunc, TCGlobal, TCProg, TCStmt, TCType, TCVDecl, TypedExp};
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+
+// every warning the compiler gives, by the name -W and -A use for it
+pub static LINTS: &[&str] = &[
+    "unused-variable",
+    "unused-parameter",
+    "unused-result",
+    "dead-assignment",
+    "shadowing",
+    "unreachable-code",
+    "div-by-zero",
+];
+
+// which warnings get reported. everything is on unless -A turns it off, naming a lint with -W
+// overrides -A, and "all" works for both. -W error (usually written -Werror) turns every
+// warning that is reported into an error
+#[derive(Debug, Default)]
+pub struct LintLevels {
+    warn: HashSet<&'static str>,
+    allow: HashSet<&'static str>,
+    warn_all: bool,
+    allow_all: bool,
+    pub werror: bool,
+}
+
+fn lookup_lint(name: &str) -> Result<&'static str> {
+    LINTS
+        .iter()
+        .find(|lint| **lint == name)
+        .cloned()
+        .ok_or(anyhow!("unknown lint: {}", name))
+}
+
+impl LintLevels {
+    pub fn from_flags<'a>(
+        warn: impl Iterator<Item = &'a str>,
+        allow: impl Iterator<Item = &'a str>,
+    ) -> Result<Self> {
+        let mut levels = LintLevels::default();
+        for name in warn {
+            match name {
+                "error" => levels.werror = true,
+                "all" => levels.warn_all = true,
+                _ => {
+                    levels.warn.insert(lookup_lint(name)?);
+                }
+            }
+        }
+        for name in allow {
+            match name {
+                "all" => levels.allow_all = true,
+                _ => {
+                    levels.allow.insert(lookup_lint(name)?);
+                }
+            }
+        }
+        Ok(levels)
+    }
+
+    pub fn enabled(&self, lint: &str) -> bool {
+        self.warn_all
+            || self.warn.contains(lint)
+            || !(self.allow_all || self.allow.contains(lint))
+    }
+}
+
+// warnings about code that typechecks but is probably a mistake. runs on the typed AST before
+// it's folded, so everything is checked as written
+pub fn lint_prog(prog: &TCProg, warnings: &mut Vec<Diagnostic>) {
+    for func in prog.funcs.iter() {
+        lint_func(func, &prog.globals, warnings);
+    }
+}
+
+fn lint_func(func: &TCFunc, globals: &[TCGlobal], warnings: &mut Vec<Diagnostic>) {
+    let mut found = vec![];
+
+    // globals are in a scope of their own under the parameters, so shadowing one is noticed,
+    // but they're never unused, since other functions can use them
+    let globals_scope = globals
+        .iter()
+        .map(|g| {
+            let var = Var {
+                span: g.span,
+                param: false,
+                is_ref: false,
+                used: true,
+            };
+            (g.vdecl.varid.clone(), var)
+        })
+        .collect();
+    let mut scopes = Scopes {
+        stack: vec![globals_scope, HashMap::new()],
+        found: &mut found,
+    };
+    for arg in func.args.iter() {
+        scopes.declare(arg, true);
+    }
+    scopes.block(&func.blk.stmts);
+    scopes.pop();
+
+    // what's assigned to a global is read by whatever runs next
+    let mut untracked = Untracked::default();
+    for g in globals.iter() {
+        untracked.untracked.insert(g.vdecl.varid.clone());
+    }
+    for arg in func.args.iter() {
+        untracked.declare(arg);
+    }
+    untracked.stmts(&func.blk.stmts);
+    let mut liveness = Liveness {
+        untracked: untracked.vars(),
+        found: &mut found,
+        report: true,
+    };
+    liveness.stmts(&func.blk.stmts, HashSet::new());
+
+    // scopes hand back unused variables in hash order
+    found.sort_by_key(|warning| warning.span.lo);
+    warnings.extend(found);
+}
+
+struct Var {
+    span: Span,
+    param: bool,
+    is_ref: bool,
+    used: bool,
+}
+
+// finds unused variables, parameters and call results, and shadowing, walking the body with
+// one map of declared variables per scope
+struct Scopes<'a> {
+    stack: Vec<HashMap<String, Var>>,
+    found: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> Scopes<'a> {
+    fn declare(&mut self, vdecl: &TCVDecl, param: bool) {
+        if self
+            .stack
+            .iter()
+            .any(|scope| scope.contains_key(&vdecl.varid))
+        {
+            self.found.push(Diagnostic::warning(
+                "shadowing",
+                vdecl.span,
+                format!("${} shadows a variable from an outer scope", vdecl.varid),
+            ));
+        }
+        let is_ref = if let TCType::Ref(_, _) = vdecl.type_ {
+            true
+        } else {
+            false
+        };
+        self.stack.last_mut().unwrap().insert(
+            vdecl.varid.clone(),
+            Var {
+                span: vdecl.span,
+                param,
+                is_ref,
+                used: false,
+            },
+        );
+    }
+
+    fn lookup(&mut self, varid: &String) -> Option<&mut Var> {
+        self.stack
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(varid))
+    }
+
+    fn push(&mut self) {
+        self.stack.push(HashMap::new());
+    }
+
+    // variables starting with an underscore are allowed to go unused
+    fn pop(&mut self) {
+        for (varid, var) in self.stack.pop().unwrap() {
+            if var.used || varid.starts_with('_') {
+                continue;
+            }
+            let (lint, what) = if var.param {
+                ("unused-parameter", "parameter")
+            } else {
+                ("unused-variable", "variable")
+            };
+            self.found.push(Diagnostic::warning(
+                lint,
+                var.span,
+                format!("unused {} ${}", what, varid),
+            ));
+        }
+    }
+
+    fn block(&mut self, stmts: &Vec<TCStmt>) {
+        self.push();
+        for stmt in stmts.iter() {
+            self.stmt(stmt);
+        }
+        self.pop();
+    }
+
+    // the body of a while or if has its own scope even when it isn't a block
+    fn scoped(&mut self, stmt: &TCStmt) {
+        self.push();
+        self.stmt(stmt);
+        self.pop();
+    }
+
+    fn stmt(&mut self, stmt: &TCStmt) {
+        match stmt {
+            TCStmt::Blk(blk) => self.block(&blk.stmts),
+            TCStmt::ReturnStmt(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(exp);
+                }
+            }
+            TCStmt::VDeclStmt { vdecl, exp } => {
+                self.exp(exp);
+                self.declare(vdecl, false);
+            }
+            TCStmt::ExpStmt(exp) => {
+                if let TCExp::FuncCall { globid, .. } = &exp.exp {
+                    if exp.type_ != TCType::VoidType {
+                        self.found.push(Diagnostic::warning(
+                            "unused-result",
+                            exp.span,
+                            format!("result of call to {} is unused", globid),
+                        ));
+                    }
+                }
+                self.exp(exp);
+            }
+            TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => self.exp(exp),
+            TCStmt::AssertEq { lhs, rhs } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCStmt::WhileStmt { cond, stmt } => {
+                self.exp(cond);
+                self.scoped(stmt);
+            }
+            TCStmt::IfStmt {
+                cond,
+                stmt,
+                else_stmt,
+            } => {
+                self.exp(cond);
+                self.scoped(stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.scoped(else_stmt);
+                }
+            }
+            TCStmt::PrintStmtSlit(_) => {}
+        }
+    }
+
+    fn exp(&mut self, exp: &TypedExp) {
+        match &exp.exp {
+            TCExp::Assign { varid, exp } => {
+                self.exp(exp);
+                // writing through a ref is how a function hands back a result
+                if let Some(var) = self.lookup(varid) {
+                    if var.is_ref {
+                        var.used = true;
+                    }
+                }
+            }
+            TCExp::Cast { exp, .. } | TCExp::UnaryOp { exp, .. } => self.exp(exp),
+            TCExp::BinOp { lhs, rhs, .. } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCExp::Literal(_) => {}
+            TCExp::VarVal(varid) => {
+                if let Some(var) = self.lookup(varid) {
+                    var.used = true;
+                }
+            }
+            TCExp::FuncCall { exps, .. } => {
+                for exp in exps.iter() {
+                    self.exp(exp);
+                }
+            }
+            TCExp::NewArray { len: exp, .. } | TCExp::Len(exp) => self.exp(exp),
+            TCExp::Index { varid, index } => {
+                self.exp(index);
+                if let Some(var) = self.lookup(varid) {
+                    var.used = true;
+                }
+            }
+            // the array might be shared, so writing into it counts as using it
+            TCExp::AssignIndex { varid, index, exp } => {
+                self.exp(index);
+                self.exp(exp);
+                if let Some(var) = self.lookup(varid) {
+                    var.used = true;
+                }
+            }
+        }
+    }
+}
+
+// liveness below goes by variable name, which is only safe for some variables. this collects
+// the ones it has to leave alone: refs, anything a ref could point at, and names declared more
+// than once in the function. variables that are never read are left to unused-variable
+#[derive(Default)]
+struct Untracked {
+    declared: HashSet<String>,
+    untracked: HashSet<String>,
+    read: HashSet<String>,
+}
+
+impl Untracked {
+    fn declare(&mut self, vdecl: &TCVDecl) {
+        if !self.declared.insert(vdecl.varid.clone()) {
+            self.untracked.insert(vdecl.varid.clone());
+        }
+        if let TCType::Ref(_, _) = vdecl.type_ {
+            self.untracked.insert(vdecl.varid.clone());
+        }
+    }
+
+    fn vars(self) -> HashSet<String> {
+        let read = self.read;
+        self.declared
+            .into_iter()
+            .filter(|varid| !read.contains(varid))
+            .chain(self.untracked.into_iter())
+            .collect()
+    }
+
+    fn stmts(&mut self, stmts: &Vec<TCStmt>) {
+        for stmt in stmts.iter() {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &TCStmt) {
+        match stmt {
+            TCStmt::Blk(blk) => self.stmts(&blk.stmts),
+            TCStmt::ReturnStmt(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(exp);
+                }
+            }
+            TCStmt::VDeclStmt { vdecl, exp } => {
+                if let (TCType::Ref(_, _), TCExp::VarVal(target)) = (vdecl.type_, &exp.exp) {
+                    self.untracked.insert(target.clone());
+                }
+                self.exp(exp);
+                self.declare(vdecl);
+            }
+            TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => self.exp(exp),
+            TCStmt::AssertEq { lhs, rhs } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCStmt::WhileStmt { cond, stmt } => {
+                self.exp(cond);
+                self.stmt(stmt);
+            }
+            TCStmt::IfStmt {
+                cond,
+                stmt,
+                else_stmt,
+            } => {
+                self.exp(cond);
+                self.stmt(stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.stmt(else_stmt);
+                }
+            }
+            TCStmt::PrintStmtSlit(_) => {}
+        }
+    }
+
+    fn exp(&mut self, exp: &TypedExp) {
+        match &exp.exp {
+            TCExp::Assign { exp, .. }
+            | TCExp::Cast { exp, .. }
+            | TCExp::UnaryOp { exp, .. }
+            | TCExp::NewArray { len: exp, .. }
+            | TCExp::Len(exp) => self.exp(exp),
+            TCExp::BinOp { lhs, rhs, .. } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCExp::Literal(_) => {}
+            TCExp::VarVal(varid) => {
+                self.read.insert(varid.clone());
+            }
+            TCExp::Index { varid, index } => {
+                self.read.insert(varid.clone());
+                self.exp(index);
+            }
+            TCExp::AssignIndex { varid, index, exp } => {
+                self.read.insert(varid.clone());
+                self.exp(index);
+                self.exp(exp);
+            }
+            TCExp::FuncCall {
+                exps,
+                expected_args,
+                ..
+            } => {
+                for (arg_type, arg) in expected_args.iter().zip(exps.iter()) {
+                    if let (TCType::Ref(_, _), TCExp::VarVal(varid)) = (arg_type, &arg.exp) {
+                        self.untracked.insert(varid.clone());
+                    }
+                    self.exp(arg);
+                }
+            }
+        }
+    }
+}
+
+// finds assignments whose value is never read, by working backwards through the body with the
+// set of variables that are read before being assigned again
+struct Liveness<'a> {
+    untracked: HashSet<String>,
+    found: &'a mut Vec<Diagnostic>,
+    // off while a loop is being iterated to a fixed point, so nothing is reported twice
+    report: bool,
+}
+
+impl<'a> Liveness<'a> {
+    fn check_assignment(&mut self, varid: &String, span: Span, live: &HashSet<String>) {
+        if self.report && !self.untracked.contains(varid) && !live.contains(varid) {
+            self.found.push(Diagnostic::warning(
+                "dead-assignment",
+                span,
+                format!("value assigned to ${} is never read", varid),
+            ));
+        }
+    }
+
+    // live is what's read after the statements, returns what's read before them
+    fn stmts(&mut self, stmts: &Vec<TCStmt>, live: HashSet<String>) -> HashSet<String> {
+        stmts
+            .iter()
+            .rev()
+            .fold(live, |live, stmt| self.stmt(stmt, live))
+    }
+
+    fn stmt(&mut self, stmt: &TCStmt, live: HashSet<String>) -> HashSet<String> {
+        match stmt {
+            TCStmt::Blk(blk) => self.stmts(&blk.stmts, live),
+            // nothing after a return runs
+            TCStmt::ReturnStmt(exp) => match exp {
+                Some(exp) => self.exp(exp, HashSet::new()),
+                None => HashSet::new(),
+            },
+            TCStmt::VDeclStmt { vdecl, exp } => {
+                self.check_assignment(&vdecl.varid, vdecl.span, &live);
+                let mut live = live;
+                live.remove(&vdecl.varid);
+                self.exp(exp, live)
+            }
+            TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => {
+                self.exp(exp, live)
+            }
+            TCStmt::AssertEq { lhs, rhs } => {
+                let live = self.exp(rhs, live);
+                self.exp(lhs, live)
+            }
+            TCStmt::WhileStmt { cond, stmt } => {
+                // the condition runs before every iteration and once more to leave the loop.
+                // iterate until what's live there stops growing, then report using that
+                let report = self.report;
+                self.report = false;
+                let mut at_cond = self.exp(cond, live.clone());
+                loop {
+                    let mut after_cond = live.clone();
+                    after_cond.extend(self.stmt(stmt, at_cond.clone()));
+                    let new_at_cond = self.exp(cond, after_cond);
+                    if new_at_cond == at_cond {
+                        break;
+                    }
+                    at_cond = new_at_cond;
+                }
+                self.report = report;
+                let mut after_cond = live;
+                after_cond.extend(self.stmt(stmt, at_cond));
+                self.exp(cond, after_cond)
+            }
+            TCStmt::IfStmt {
+                cond,
+                stmt,
+                else_stmt,
+            } => {
+                let mut after_cond = self.stmt(stmt, live.clone());
+                match else_stmt {
+                    Some(else_stmt) => after_cond.extend(self.stmt(else_stmt, live)),
+                    None => after_cond.extend(live),
+                }
+                self.exp(cond, after_cond)
+            }
+            TCStmt::PrintStmtSlit(_) => live,
+        }
+    }
+
+    // expressions are evaluated left to right, so they're walked right to left
+    fn exp(&mut self, exp: &TypedExp, live: HashSet<String>) -> HashSet<String> {
+        match &exp.exp {
+            TCExp::Assign { varid, exp: value } => {
+                self.check_assignment(varid, exp.span, &live);
+                let mut live = live;
+                if !self.untracked.contains(varid) {
+                    live.remove(varid);
+                }
+                self.exp(value, live)
+            }
+            TCExp::Cast { exp, .. } | TCExp::UnaryOp { exp, .. } => self.exp(exp, live),
+            TCExp::BinOp { lhs, rhs, .. } => {
+                let live = self.exp(rhs, live);
+                self.exp(lhs, live)
+            }
+            TCExp::Literal(_) => live,
+            TCExp::VarVal(varid) => {
+                let mut live = live;
+                live.insert(varid.clone());
+                live
+            }
+            TCExp::NewArray { len: exp, .. } | TCExp::Len(exp) => self.exp(exp, live),
+            // the index is worked out before the array is looked up
+            TCExp::Index { varid, index } => {
+                let mut live = live;
+                live.insert(varid.clone());
+                self.exp(index, live)
+            }
+            TCExp::AssignIndex { varid, index, exp } => {
+                let mut live = live;
+                live.insert(varid.clone());
+                let live = self.exp(exp, live);
+                self.exp(index, live)
+            }
+            TCExp::FuncCall { exps, .. } => exps
+                .iter()
+                .rev()
+                .fold(live, |live, exp| self.exp(exp, live)),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+extern crate serde_json;
+
+use ekcc::lsp::{self, Analysis, Position, Range, Severity, SymbolKind};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// ekcc-lsp: a language server for .ek files, speaking json-rpc over stdin/stdout. all the
+// actual work is lsp::analyze, redone whenever a file is opened or changed
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+struct Server {
+    // open documents by uri, with their last analysis
+    docs: HashMap<String, Analysis>,
+    shutdown: bool,
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut server = Server {
+        docs: HashMap::new(),
+        shutdown: false,
+    };
+    while let Some(message) = read_message(&mut input) {
+        let method = match message["method"].as_str() {
+            Some(method) => method.to_string(),
+            // a response to something we never asked, or garbage
+            None => continue,
+        };
+        if method == "exit" {
+            std::process::exit(if server.shutdown { 0 } else { 1 });
+        }
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.request(&method, params) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    Err((code, msg)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": code, "message": msg},
+                    }),
+                };
+                write_message(&mut output, &response);
+            }
+            None => {
+                for notification in server.notify(&method, params) {
+                    write_message(&mut output, &notification);
+                }
+            }
+        }
+    }
+}
+
+impl Server {
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": {"openClose": true, "change": 1, "save": true},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": {"name": "ekcc-lsp", "version": "1.0"},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let (analysis, pos) = self.doc_position(params)?;
+                Ok(match analysis.hover(pos) {
+                    Some((range, text)) => json!({
+                        "contents": {"kind": "plaintext", "value": text},
+                        "range": range_json(range),
+                    }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/definition" => {
+                let (analysis, pos) = self.doc_position(params)?;
+                Ok(match analysis.definition(pos) {
+                    Some(location) => json!({
+                        "uri": path_to_uri(&location.filename),
+                        "range": range_json(location.range),
+                    }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/documentSymbol" => {
+                let analysis = self.doc(params)?;
+                let symbols: Vec<Value> = analysis
+                    .symbols
+                    .iter()
+                    .map(|symbol| {
+                        json!({
+                            "name": symbol.name,
+                            "detail": match symbol.kind {
+                                SymbolKind::Def => "def",
+                                SymbolKind::Extern => "extern",
+                                SymbolKind::Test => "test",
+                                SymbolKind::Global => "global",
+                                SymbolKind::Const => "const",
+                            },
+                            // Method, Variable, Constant and Function
+                            "kind": match symbol.kind {
+                                SymbolKind::Test => 6,
+                                SymbolKind::Global => 13,
+                                SymbolKind::Const => 14,
+                                _ => 12,
+                            },
+                            "range": range_json(symbol.range),
+                            "selectionRange": range_json(symbol.range),
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(symbols))
+            }
+            "textDocument/completion" => {
+                let analysis = self.doc(params)?;
+                let items: Vec<Value> = analysis
+                    .functions
+                    .iter()
+                    .map(|function| {
+                        json!({
+                            "label": function.name,
+                            // Function
+                            "kind": 3,
+                            "detail": function.signature,
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(items))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported request {}", method))),
+        }
+    }
+
+    // handles a notification, returning the notifications to send back
+    fn notify(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => return vec![],
+        };
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // full sync, so the last change is the whole file
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didSave" => params["text"].as_str(),
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                return vec![diagnostics(&uri, None)];
+            }
+            _ => return vec![],
+        };
+        // a save without text re-reads the file, since imports it depends on may have changed
+        let text = match text {
+            Some(text) => text.to_string(),
+            None => match std::fs::read_to_string(uri_to_path(&uri)) {
+                Ok(text) => text,
+                Err(_) => return vec![],
+            },
+        };
+        let analysis = lsp::analyze(&uri_to_path(&uri), &text);
+        let published = diagnostics(&uri, Some(&analysis));
+        self.docs.insert(uri, analysis);
+        vec![published]
+    }
+
+    fn doc(&self, params: &Value) -> Result<&Analysis, (i64, String)> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "no textDocument.uri".to_string()))?;
+        self.docs
+            .get(uri)
+            .ok_or((INVALID_PARAMS, format!("{} is not open", uri)))
+    }
+
+    fn doc_position(&self, params: &Value) -> Result<(&Analysis, Position), (i64, String)> {
+        let pos = &params["position"];
+        match (pos["line"].as_u64(), pos["character"].as_u64()) {
+            (Some(line), Some(character)) => Ok((
+                self.doc(params)?,
+                Position {
+                    line: line as u32,
+                    character: character as u32,
+                },
+            )),
+            _ => Err((INVALID_PARAMS, "no position".to_string())),
+        }
+    }
+}
+
+// a publishDiagnostics notification for uri, clearing them if there's no analysis
+fn diagnostics(uri: &str, analysis: Option<&Analysis>) -> Value {
+    let problems: Vec<Value> = analysis
+        .map(|analysis| analysis.problems.as_slice())
+        .unwrap_or(&[])
+        .iter()
+        .map(|problem| {
+            json!({
+                "range": range_json(problem.range),
+                "severity": match problem.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                },
+                "source": "ekcc",
+                "message": problem.msg,
+            })
+        })
+        .collect();
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": problems},
+    })
+}
+
+fn range_json(range: Range) -> Value {
+    json!({
+        "start": {"line": range.start.line, "character": range.start.character},
+        "end": {"line": range.end.line, "character": range.end.character},
+    })
+}
+
+// file:///a/b%20c.ek -> /a/b c.ek. anything else is used as is
+fn uri_to_path(uri: &str) -> String {
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => path,
+        None => return uri.to_string(),
+    };
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let path = match std::fs::canonicalize(path) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => path.to_string(),
+    };
+    let mut uri = "file://".to_string();
+    for byte in path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+// one Content-Length framed message, or None at the end of input
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if input.read_line(&mut header).ok()? == 0 {
+                return None;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0; length?];
+        input.read_exact(&mut body).ok()?;
+        if let Ok(message) = serde_json::from_slice(&body) {
+            return Some(message);
+        }
+    }
+}
+
+fn write_message(output: &mut impl Write, message: &Value) {
+    let body = message.to_string();
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = output.flush();
+}
@@ -0,0 +1,492 @@
+use crate::ast::{Diagnostic, LineIndex, Prog, Span};
+use crate::lint::{lint_prog, LintLevels};
+use crate::modules;
+use crate::parse::parse_recovering;
+use crate::typecheck::{
+    function_types, signature, typecheck_module, TCExp, TCFunc, TCProg, TCStmt, TCType, TypedExp,
+};
+use std::collections::HashMap;
+
+// what ekcc-lsp knows about one .ek file: its problems, its symbols, and for each place in it
+// a type to show on hover and where whatever's there is defined. the protocol side is in
+// lsp-main.rs
+
+// counted from 0, with character in utf-16 code units, like the language server protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub filename: String,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub range: Range,
+    pub severity: Severity,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Def,
+    Extern,
+    Test,
+    Global,
+    Const,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub range: Range,
+}
+
+// a function that can be called from the file, for completion
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: String,
+    pub signature: String,
+}
+
+pub struct Analysis {
+    src: String,
+    lines: LineIndex,
+    pub problems: Vec<Problem>,
+    pub symbols: Vec<Symbol>,
+    pub functions: Vec<Function>,
+    // innermost span wins for both
+    hovers: Vec<(Span, String)>,
+    definitions: Vec<(Span, Location)>,
+}
+
+impl Analysis {
+    pub fn hover(&self, pos: Position) -> Option<(Range, String)> {
+        innermost(&self.hovers, self.offset(pos))
+            .map(|(span, text)| (self.range(*span), text.clone()))
+    }
+
+    pub fn definition(&self, pos: Position) -> Option<Location> {
+        innermost(&self.definitions, self.offset(pos)).map(|(_, location)| location.clone())
+    }
+
+    fn range(&self, span: Span) -> Range {
+        to_range(&self.src, &self.lines, span)
+    }
+
+    fn offset(&self, pos: Position) -> usize {
+        let mut line_start = 0;
+        for _ in 0..pos.line {
+            match self.src[line_start..].find('\n') {
+                Some(newline) => line_start += newline + 1,
+                None => return self.src.len(),
+            }
+        }
+        let mut units = 0;
+        for (i, c) in self.src[line_start..].char_indices() {
+            if units >= pos.character as usize || c == '\n' {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.src.len()
+    }
+}
+
+// parse, load the imports of, typecheck and lint filename, whose text is src. filename's
+// imports are read from disk
+pub fn analyze(filename: &str, src: &str) -> Analysis {
+    let mut analysis = Analysis {
+        src: src.to_string(),
+        lines: LineIndex::new(src),
+        problems: vec![],
+        symbols: vec![],
+        functions: vec![],
+        hovers: vec![],
+        definitions: vec![],
+    };
+
+    // a file with syntax errors still gets its symbols, from whatever parsed
+    let (prog, errors) = parse_recovering(src);
+    if let Some(prog) = &prog {
+        analysis.symbols = symbols(prog, &analysis);
+    }
+    if prog.is_none() || !errors.is_empty() {
+        for error in errors {
+            analysis.error(error.span, error.msg);
+        }
+        return analysis;
+    }
+
+    let mut loaded = match modules::load_with_src(filename, src.to_string()) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            // errors in this file come back as filename:line:col: msg
+            let msg = err.to_string();
+            match located_offset(filename, &msg, src) {
+                Some((offset, msg)) => analysis.error(Span::new(offset, offset), msg.to_string()),
+                None => analysis.error(Span::default(), msg.clone()),
+            }
+            return analysis;
+        }
+    };
+    let main = loaded.pop().unwrap();
+
+    // every function this file calls, or can call, by name: its own and the pub ones of the
+    // files it imports (already renamed module::name)
+    let mut definitions = HashMap::new();
+    for import in loaded.iter() {
+        let lines = LineIndex::new(&import.src);
+        for func in import.prog.funcs.iter().filter(|func| func.public) {
+            let location = Location {
+                filename: import.filename.clone(),
+                range: to_range(&import.src, &lines, func.span),
+            };
+            definitions.insert(func.globid.clone(), location);
+        }
+    }
+    for (globid, span) in main
+        .prog
+        .externs
+        .iter()
+        .map(|e| (&e.globid, e.span))
+        .chain(main.prog.funcs.iter().map(|f| (&f.globid, f.span)))
+    {
+        if !definitions.contains_key(globid) {
+            let location = Location {
+                filename: filename.to_string(),
+                range: analysis.range(span),
+            };
+            definitions.insert(globid.clone(), location);
+        }
+    }
+
+    let signatures = match function_types(&main.prog) {
+        Ok(signatures) => signatures,
+        Err(err) => {
+            analysis.report(err);
+            return analysis;
+        }
+    };
+    let mut functions: Vec<Function> = signatures
+        .iter()
+        .filter(|(name, _)| !name.starts_with("test \""))
+        .map(|(name, (ret, args))| Function {
+            name: name.clone(),
+            signature: signature(*ret, args),
+        })
+        .collect();
+    for import in loaded.iter() {
+        let import_signatures = match function_types(&import.prog) {
+            Ok(import_signatures) => import_signatures,
+            Err(_) => continue,
+        };
+        for func in import.prog.funcs.iter() {
+            if func.public && !signatures.contains_key(&func.globid) {
+                let (ret, args) = &import_signatures[&func.globid];
+                functions.push(Function {
+                    name: func.globid.clone(),
+                    signature: signature(*ret, args),
+                });
+            }
+        }
+    }
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    analysis.functions = functions;
+
+    let mut warnings = vec![];
+    let typed = match typecheck_module(main.prog, &mut warnings) {
+        Ok(typed) => typed,
+        Err(err) => {
+            analysis.report(err);
+            return analysis;
+        }
+    };
+    lint_prog(&typed, &mut warnings);
+    let levels = LintLevels::default();
+    for warning in warnings {
+        if levels.enabled(warning.lint.unwrap_or("")) {
+            let range = analysis.range(warning.span);
+            analysis.problems.push(Problem {
+                range,
+                severity: Severity::Warning,
+                msg: format!("{} [{}]", warning.msg, warning.lint.unwrap_or("")),
+            });
+        }
+    }
+
+    let mut indexer = Indexer {
+        analysis: &mut analysis,
+        filename,
+        signatures: &signatures,
+        functions: &definitions,
+        scopes: vec![],
+    };
+    indexer.prog(&typed);
+    analysis
+}
+
+impl Analysis {
+    fn error(&mut self, span: Span, msg: String) {
+        let range = self.range(span);
+        self.problems.push(Problem {
+            range,
+            severity: Severity::Error,
+            msg,
+        });
+    }
+
+    // typechecker errors only sometimes know where they are
+    fn report(&mut self, err: anyhow::Error) {
+        let span = match err.downcast_ref::<Diagnostic>() {
+            Some(diagnostic) => diagnostic.span,
+            None => Span::default(),
+        };
+        self.error(span, err.to_string());
+    }
+}
+
+fn symbols(prog: &Prog, analysis: &Analysis) -> Vec<Symbol> {
+    let externs = prog.externs.iter().map(|e| Symbol {
+        name: e.globid.clone(),
+        kind: SymbolKind::Extern,
+        range: analysis.range(e.span),
+    });
+    let globals = prog.globals.iter().map(|g| Symbol {
+        name: format!("${}", g.vdecl.varid),
+        kind: if g.constant {
+            SymbolKind::Const
+        } else {
+            SymbolKind::Global
+        },
+        range: analysis.range(g.span),
+    });
+    let funcs = prog.funcs.iter().map(|f| match &f.test {
+        Some(name) => Symbol {
+            name: name.clone(),
+            kind: SymbolKind::Test,
+            range: analysis.range(f.span),
+        },
+        None => Symbol {
+            name: f.globid.clone(),
+            kind: SymbolKind::Def,
+            range: analysis.range(f.span),
+        },
+    });
+    externs.chain(globals).chain(funcs).collect()
+}
+
+// walks the typed program, recording a hover for every expression and declaration and a
+// definition for every use of a variable or function
+struct Indexer<'a> {
+    analysis: &'a mut Analysis,
+    filename: &'a str,
+    signatures: &'a HashMap<String, (TCType, Vec<TCType>)>,
+    functions: &'a HashMap<String, Location>,
+    // the declarations of the variables in scope, innermost block last
+    scopes: Vec<HashMap<String, Span>>,
+}
+
+impl<'a> Indexer<'a> {
+    fn prog(&mut self, prog: &TCProg) {
+        self.scoped(|indexer| {
+            for global in prog.globals.iter() {
+                let vdecl = &global.vdecl;
+                indexer.declare(&vdecl.varid, vdecl.type_, vdecl.span);
+            }
+            for func in prog.funcs.iter() {
+                indexer.func(func);
+            }
+        });
+    }
+
+    fn func(&mut self, func: &TCFunc) {
+        self.scopes.push(HashMap::new());
+        for arg in func.args.iter() {
+            self.declare(&arg.varid, arg.type_, arg.span);
+        }
+        self.scoped(|indexer| {
+            for stmt in func.blk.stmts.iter() {
+                indexer.stmt(stmt);
+            }
+        });
+        self.scopes.pop();
+    }
+
+    fn scoped<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, varid: &str, type_: TCType, span: Span) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(varid.to_string(), span);
+        self.hover(span, format!("${}: {}", varid, type_));
+    }
+
+    fn hover(&mut self, span: Span, text: String) {
+        self.analysis.hovers.push((span, text));
+    }
+
+    // name_len bytes from the start of span, where the name of what's used is
+    fn define(&mut self, span: Span, name_len: usize, location: Location) {
+        let name_span = Span::new(span.lo, (span.lo + name_len).min(span.hi));
+        self.analysis.definitions.push((name_span, location));
+    }
+
+    fn use_var(&mut self, varid: &str, span: Span) {
+        let decl = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(varid))
+            .cloned();
+        if let Some(decl) = decl {
+            let location = Location {
+                filename: self.filename.to_string(),
+                range: self.analysis.range(decl),
+            };
+            // $ and the name
+            self.define(span, varid.len() + 1, location);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &TCStmt) {
+        match stmt {
+            TCStmt::Blk(blk) => self.scoped(|indexer| {
+                for stmt in blk.stmts.iter() {
+                    indexer.stmt(stmt);
+                }
+            }),
+            TCStmt::ReturnStmt(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(exp);
+                }
+            }
+            TCStmt::VDeclStmt { vdecl, exp } => {
+                self.exp(exp);
+                self.declare(&vdecl.varid, vdecl.type_, vdecl.span);
+            }
+            TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => self.exp(exp),
+            TCStmt::AssertEq { lhs, rhs } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCStmt::WhileStmt { cond, stmt } => {
+                self.exp(cond);
+                self.scoped(|indexer| indexer.stmt(stmt));
+            }
+            TCStmt::IfStmt {
+                cond,
+                stmt,
+                else_stmt,
+            } => {
+                self.exp(cond);
+                self.scoped(|indexer| indexer.stmt(stmt));
+                if let Some(else_stmt) = else_stmt {
+                    self.scoped(|indexer| indexer.stmt(else_stmt));
+                }
+            }
+            TCStmt::PrintStmtSlit(_) => {}
+        }
+    }
+
+    fn exp(&mut self, exp: &TypedExp) {
+        self.hover(exp.span, exp.type_.to_string());
+        match &exp.exp {
+            TCExp::Assign { varid, exp: rhs } => {
+                self.use_var(varid, exp.span);
+                self.exp(rhs);
+            }
+            TCExp::Cast { exp, .. } | TCExp::UnaryOp { exp, .. } => self.exp(exp),
+            TCExp::BinOp { lhs, rhs, .. } => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            TCExp::Literal(_) => {}
+            TCExp::VarVal(varid) => self.use_var(varid, exp.span),
+            TCExp::NewArray { len: exp, .. } | TCExp::Len(exp) => self.exp(exp),
+            TCExp::Index { varid, index } => {
+                self.use_var(varid, exp.span);
+                self.exp(index);
+            }
+            TCExp::AssignIndex {
+                varid,
+                index,
+                exp: rhs,
+            } => {
+                self.use_var(varid, exp.span);
+                self.exp(index);
+                self.exp(rhs);
+            }
+            TCExp::FuncCall { globid, exps, .. } => {
+                let name_span = Span::new(exp.span.lo, exp.span.lo + globid.len());
+                if let Some((ret, args)) = self.signatures.get(globid) {
+                    self.hover(name_span, format!("{}: {}", globid, signature(*ret, args)));
+                }
+                if let Some(location) = self.functions.get(globid).cloned() {
+                    self.define(exp.span, globid.len(), location);
+                }
+                for exp in exps.iter() {
+                    self.exp(exp);
+                }
+            }
+        }
+    }
+}
+
+// the entry with the smallest span around offset
+fn innermost<T>(entries: &[(Span, T)], offset: usize) -> Option<&(Span, T)> {
+    entries
+        .iter()
+        .filter(|(span, _)| span.lo <= offset && offset < span.hi.max(span.lo + 1))
+        .min_by_key(|(span, _)| span.hi - span.lo)
+}
+
+fn to_range(src: &str, lines: &LineIndex, span: Span) -> Range {
+    Range {
+        start: to_position(src, lines, span.lo),
+        end: to_position(src, lines, span.hi),
+    }
+}
+
+fn to_position(src: &str, lines: &LineIndex, offset: usize) -> Position {
+    let offset = offset.min(src.len());
+    let (line, col) = lines.line_col(offset);
+    let line_start = offset - (col as usize - 1);
+    Position {
+        line: line - 1,
+        character: src[line_start..offset].encode_utf16().count() as u32,
+    }
+}
+
+// the byte offset and message of an error formatted as filename:line:col: msg
+fn located_offset<'m>(filename: &str, msg: &'m str, src: &str) -> Option<(usize, &'m str)> {
+    let rest = msg.strip_prefix(filename)?.strip_prefix(':')?;
+    let mut parts = rest.splitn(3, ':');
+    let line: usize = parts.next()?.parse().ok()?;
+    let col: usize = parts.next()?.parse().ok()?;
+    let msg = parts.next()?.trim_start();
+    let line_start: usize = src.split('\n').take(line - 1).map(|l| l.len() + 1).sum();
+    Some(((line_start + col - 1).min(src.len()), msg))
+}
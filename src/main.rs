@@ -1,92 +1,286 @@
 extern crate clap;
 
 extern crate lalrpop;
-#[macro_use]
-extern crate lalrpop_util;
-mod ast;
-mod jit;
-mod typecheck;
-mod optimize;
-use clap::{App, Arg, Values};
-use std::fs::{read_to_string, File};
-
-lalrpop_mod!(pub kaleidoscope); // synthesized by LALRPOP
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand, Values};
+use ekcc::{ast, bench, dylib, fold, host, jit, lint, modules, optimize, typecheck};
+use std::fs::File;
 
 fn main() {
+    // -O's level has to be attached to it, or `-O in.ek` would take the input file as the level.
+    // clap only allows that as -O=2, so -O2 is rewritten to it. arguments after -- belong to the
+    // program and are left alone
+    let mut program_args = false;
+    let args = std::env::args_os().map(|arg| {
+        program_args |= arg == "--";
+        match arg.to_str() {
+            Some(opt) if !program_args && opt.len() == 3 && opt.starts_with("-O") => {
+                format!("-O={}", &opt[2..]).into()
+            }
+            _ => arg,
+        }
+    });
     let matches = App::new("ekcc")
         .version("1.0")
         .author("Julian Beckman & Claudia Richoux")
         .about("MPCS Compilers Autumn 2020 project")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
         .args(&[
             Arg::from_usage("-v, --verbose 'verbose mode. only warnings will be emitted otherwise for any correct inputs.'"),
-            Arg::from_usage("-O 'enable optimizations'"),
-            Arg::from_usage("--emit-ast 'output format will contain serialized format for AST'").conflicts_with("emit-llvm"),
+            Arg::from_usage("-W [lint]... 'report the named warning even if -A turns it off (all for every one), or -Werror to make warnings errors'").number_of_values(1),
+            Arg::from_usage("-A [lint]... 'stop reporting the named warning, or all of them with -A all'").number_of_values(1),
+            Arg::from_usage("-O [level] 'optimization level: 0, 1, 2, 3 or s (-O alone means -O3)'")
+                .possible_values(&["0", "1", "2", "3", "s"])
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true),
+            Arg::from_usage("--passes [passes] 'comma-separated llvm passes to run in order after -O, e.g. mem2reg,instcombine,gvn'").conflicts_with("f"),
+            Arg::from_usage("-g 'emit debug info so jitted code and emitted llvm can be debugged with gdb/lldb'"),
+            Arg::from_usage("--link-lib [library]... 'shared library to look for extern functions in when jitting, e.g. libm.so'").number_of_values(1),
+            Arg::from_usage("-l [name]... 'same as --link-lib lib<name>.so, e.g. -l m'").number_of_values(1),
+            Arg::from_usage("--emit-ast 'output format will contain serialized format for AST, after constant folding and dead code removal'").conflicts_with("emit-llvm"),
             Arg::from_usage("--jit 'JIT compile and run the code in input-file, any program output will go into output-file'").conflicts_with("emit-llvm").conflicts_with("emit-ast"),
-            Arg::from_usage("--time 'compiler will emit timing information for optimization passes and running time'").conflicts_with("O").conflicts_with("emit-llvm").conflicts_with("emit-ast"),
+            Arg::from_usage("--entry [name] 'with --jit, call name instead of run, with args parsed as its parameter types, and print what it returns'").requires("jit"),
+            Arg::from_usage("--time 'compiler will emit timing information for optimization passes and running time'").conflicts_with("emit-llvm").conflicts_with("emit-ast"),
+            Arg::from_usage("--time-passes [format] 'report time and IR size before/after each optimization pass on stderr, as a table (default) or csv'")
+                .possible_values(&["table", "csv"])
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true),
             Arg::from_usage("--emit-llvm 'produce the LLVM IR (unoptimized unless -O is provided)'"),
-            Arg::from_usage("-f [flag]... 'apply the specified optimization during compilation'").conflicts_with("O"),
+            Arg::from_usage("--emit-bc 'produce LLVM bitcode for ekcc link. the input file does not need a run function'")
+                .conflicts_with("emit-llvm")
+                .conflicts_with("emit-ast")
+                .conflicts_with("jit"),
+            Arg::from_usage("-f [flag]... 'apply the specified optimization during compilation, in the order given'").conflicts_with("O"),
+            Arg::from_usage("--repeat [n] 'run the -f/--passes pipeline n times (default 1)'"),
+            Arg::from_usage("--fixed-point 'rerun the -f/--passes pipeline until the IR stops changing'").conflicts_with("repeat"),
             Arg::from_usage("-o <output-file> 'required output file'"),
             Arg::from_usage("<input-file> 'sets the input file to use'"),
             Arg::from_usage("[args]... 'arguments to pass to just-in-time compiled program'"),
         ])
-        .get_matches();
-    let input_filename = matches.value_of("input-file").unwrap();
-    let output_filename = matches.value_of("o").unwrap();
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("compile and run a program under each optimization group, reporting timings and writing them to csv")
+                .args(&[
+                    Arg::from_usage("--warmup [n] 'untimed runs before measuring each group (default 1)'"),
+                    Arg::from_usage("--iterations [n] 'measured runs per group (default 5)'"),
+                    Arg::from_usage("--csv [file] 'where to write results (default results/test_<name>-aa.csv)'"),
+                    Arg::from_usage("--link-lib [library]... 'shared library to look for extern functions in'").number_of_values(1),
+                    Arg::from_usage("-l [name]... 'same as --link-lib lib<name>.so'").number_of_values(1),
+                    Arg::from_usage("<input-file> 'sets the input file to use'"),
+                    Arg::from_usage("[args]... 'arguments to pass to the program'"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("run each test \"name\" { ... } in a file in a process of its own and report which failed")
+                .args(&[
+                    Arg::from_usage("--link-lib [library]... 'shared library to look for extern functions in'").number_of_values(1),
+                    Arg::from_usage("-l [name]... 'same as --link-lib lib<name>.so'").number_of_values(1),
+                    Arg::from_usage("<input-file> 'sets the input file to use'"),
+                    Arg::from_usage("[args]... 'arguments for arg and argf'"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("link")
+                .about("link bitcode files written with --emit-bc into one program")
+                .args(&[
+                    Arg::from_usage("--emit-llvm 'write the linked program as LLVM IR instead of bitcode'"),
+                    Arg::from_usage("--jit 'run the linked program after writing it'"),
+                    Arg::from_usage("--link-lib [library]... 'shared library to look for extern functions in with --jit'").number_of_values(1),
+                    Arg::from_usage("-l [name]... 'same as --link-lib lib<name>.so'").number_of_values(1),
+                    Arg::from_usage("-o <output-file> 'required output file'"),
+                    Arg::from_usage("<input-files>... 'bitcode files to link'"),
+                    Arg::from_usage("[args]... 'arguments to pass to the program with --jit, after --'").last(true),
+                ]),
+        )
+        .get_matches_from(args);
 
-    let file_contents_str = read_to_string(input_filename).expect("could not open input file");
-    let prog = kaleidoscope::ProgParser::new().parse(&file_contents_str);
-    if let Err(msg) = prog {
-        println!("error: {}", msg);
-        std::process::exit(1);
+    if let Some(link_matches) = matches.subcommand_matches("link") {
+        let input_filenames: Vec<&str> = link_matches.values_of("input-files").unwrap().collect();
+        let output_filename = link_matches.value_of("o").unwrap();
+        if let Err(msg) = jit::link(
+            &input_filenames,
+            output_filename,
+            link_matches.is_present("emit-llvm"),
+        ) {
+            println!("error: {}", msg);
+            std::process::exit(1);
+        }
+        if link_matches.is_present("jit") {
+            let args = link_matches
+                .values_of("args")
+                .unwrap_or(Values::default())
+                .map(|a| a.to_string())
+                .collect();
+            let libs = open_libs(link_matches);
+            match jit::jit_linked(&input_filenames, &host::HostFns::with_args(args), &libs) {
+                Err(e) => {
+                    println!("error: {}", e);
+                    std::process::exit(1);
+                }
+                Ok(rc) => std::process::exit(rc),
+            }
+        }
+        return;
+    }
+
+    if let Some(test_matches) = matches.subcommand_matches("test") {
+        let input_filename = test_matches.value_of("input-file").unwrap();
+        let modules = load_prog(input_filename, &lint::LintLevels::default(), false);
+        let options = jit::CompileOptions {
+            opt_level: optimize::OptLevel::O0,
+            pipeline: optimize::Pipeline::default(),
+            time: false,
+            time_passes: None,
+            debug: false,
+            libs: open_libs(test_matches),
+            entry: None,
+        };
+        let args = test_matches
+            .values_of("args")
+            .unwrap_or(Values::default())
+            .map(|a| a.to_string())
+            .collect();
+        match jit::test(modules, &host::HostFns::with_args(args), &options) {
+            Err(e) => {
+                println!("error: {}", e);
+                std::process::exit(1);
+            }
+            Ok(passed) => std::process::exit(if passed { 0 } else { 1 }),
+        }
     }
 
-    let prog = prog.unwrap();
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        let input_filename = bench_matches.value_of("input-file").unwrap();
+        let modules = load_prog(input_filename, &lint::LintLevels::default(), true);
+        let count = |name: &str, default: u32| -> u32 {
+            match bench_matches.value_of(name) {
+                None => default,
+                Some(n) => n.parse().unwrap_or_else(|msg| {
+                    println!("error: bad --{} count {}: {}", name, n, msg);
+                    std::process::exit(1);
+                }),
+            }
+        };
+        let bench_options = bench::BenchOptions {
+            warmup: count("warmup", 1),
+            iterations: count("iterations", 5),
+            csv_path: bench_matches.value_of("csv").map(|path| path.to_string()),
+            libs: open_libs(bench_matches),
+        };
+        let args = bench_matches
+            .values_of("args")
+            .unwrap_or(Values::default())
+            .map(|a| a.to_string())
+            .collect();
+        let host = host::HostFns::with_args(args);
+        if let Err(msg) = bench::bench(modules, &host, bench_options) {
+            println!("error: {}", msg);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let typed_prog = typecheck::typecheck(prog);
-    if let Err(msg) = typed_prog {
+    let input_filename = matches.value_of("input-file").unwrap();
+    let output_filename = matches.value_of("o").unwrap();
+
+    let lint_levels = lint::LintLevels::from_flags(
+        matches.values_of("W").unwrap_or(Values::default()),
+        matches.values_of("A").unwrap_or(Values::default()),
+    )
+    .unwrap_or_else(|msg| {
         println!("error: {}", msg);
         std::process::exit(1);
-    }
-
-    let typed_prog = typed_prog.unwrap();
+    });
+    // a library compiled on its own, or a program started from another function, needs no run
+    let needs_run = !matches.is_present("emit-bc") && !matches.is_present("entry");
+    let modules = load_prog(input_filename, &lint_levels, needs_run);
 
     let out_file = File::create(output_filename)
         .expect(&format!("failed to create output file at {}", output_filename).to_string());
 
-    let mut opt = false;
-    if matches.is_present("O") {
-        opt = true;
-    }
+    let opt_level = if matches.is_present("O") {
+        match matches.value_of("O").unwrap_or("3").parse() {
+            Ok(level) => level,
+            Err(msg) => {
+                println!("error: {}", msg);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        optimize::OptLevel::O0
+    };
 
-    let mut oflags = optimize::OFlags::default();
+    let passes = match optimize::parse_pass_list(matches.value_of("passes").unwrap_or("")) {
+        Ok(passes) => passes,
+        Err(msg) => {
+            println!("error: {}", msg);
+            std::process::exit(1);
+        }
+    };
+
+    // -f flags run in the order they were given, same as --passes
+    let mut pipeline = optimize::Pipeline::default();
+    pipeline.passes = passes;
     for f in matches.values_of("f").unwrap_or(Values::default()) {
-        match f {
-            "argument_promotion"         => oflags.argument_promotion = true,
-            "basic_alias_analysis"       => oflags.basic_alias_analysis = true,
-            "function_inlining"          => oflags.function_inlining = true,
-            "cfg_simplification"         => oflags.cfg_simplification = true,
-            "aggressive_dce"             => oflags.aggressive_dce = true,
-            "strip_dead_prototypes"      => oflags.strip_dead_prototypes = true,
-            "ind_var_simplify"           => oflags.ind_var_simplify = true,
-            "loop_vectorize"             => oflags.loop_vectorize = true,
-            "reassociate"                => oflags.reassociate = true,
-            "sccp"                       => oflags.sccp = true,
-            "instruction_combining"      => oflags.instruction_combining = true,
-            "promote_memory_to_register" => oflags.promote_memory_to_register = true,
-            "dead_arg_elimination"       => oflags.dead_arg_elimination = true,
-            _ => println!("ignoring invalid option: {}", f),
+        match optimize::lookup_pass(f) {
+            Some(pass) => pipeline.passes.push(pass),
+            None => println!("ignoring invalid option: {}", f),
+        }
+    }
+    if matches.is_present("fixed-point") {
+        pipeline.repeat = optimize::Repeat::FixedPoint;
+    } else if let Some(n) = matches.value_of("repeat") {
+        match n.parse() {
+            Ok(n) => pipeline.repeat = optimize::Repeat::Times(n),
+            Err(msg) => {
+                println!("error: bad --repeat count {}: {}", n, msg);
+                std::process::exit(1);
+            }
         }
     }
 
     let time = matches.is_present("time");
+    let time_passes = if matches.is_present("time-passes") {
+        match matches.value_of("time-passes") {
+            Some("csv") => Some(optimize::TimingFormat::Csv),
+            _ => Some(optimize::TimingFormat::Table),
+        }
+    } else {
+        None
+    };
+
+    let options = jit::CompileOptions {
+        opt_level,
+        pipeline,
+        time,
+        time_passes,
+        debug: matches.is_present("g"),
+        libs: open_libs(&matches),
+        entry: matches.value_of("entry").map(|name| jit::Entry {
+            name: name.to_string(),
+            args: matches
+                .values_of("args")
+                .unwrap_or(Values::default())
+                .map(|a| a.to_string())
+                .collect(),
+        }),
+    };
 
     if matches.is_present("emit-ast") {
-        if let Err(msg) = serde_yaml::to_writer(out_file, &typed_prog) {
+        if let Err(msg) = serde_yaml::to_writer(out_file, &modules.last().unwrap().prog) {
             println!("error: {}", msg);
             std::process::exit(1);
         }
     } else if matches.is_present("emit-llvm") {
-        if let Err(msg) = jit::emit_llvm(input_filename, output_filename, typed_prog, opt, oflags) {
+        if let Err(msg) = jit::emit_llvm(modules, output_filename, &options) {
+            println!("error: {}", msg);
+            std::process::exit(1);
+        }
+    } else if matches.is_present("emit-bc") {
+        if let Err(msg) = jit::emit_bitcode(modules, output_filename, &options) {
             println!("error: {}", msg);
             std::process::exit(1);
         }
@@ -96,7 +290,7 @@ fn main() {
         for a in matches.values_of("args").unwrap_or(Values::default()) {
             arg_strings.push(a.to_string());
         }
-        match jit::jit(input_filename, typed_prog, arg_strings, opt, oflags, time) {
+        match jit::jit(modules, &host::HostFns::with_args(arg_strings), &options) {
             Err(e) => {
                 println!("error: {}", e);
                 std::process::exit(1);
@@ -110,19 +304,145 @@ fn main() {
     }
 }
 
+// load the libraries given with --link-lib, then the ones given with -l, exiting if one can't
+// be opened
+fn open_libs(matches: &ArgMatches) -> Vec<dylib::SharedLib> {
+    let link_libs = matches
+        .values_of("link-lib")
+        .unwrap_or(Values::default())
+        .map(|lib| lib.to_string());
+    let l_libs = matches
+        .values_of("l")
+        .unwrap_or(Values::default())
+        .map(dylib::lib_filename);
+    link_libs
+        .chain(l_libs)
+        .map(|lib| {
+            dylib::open(&lib).unwrap_or_else(|msg| {
+                println!("error: {}", msg);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+// print the error and exit, with file:line:col when the error points into the source
+fn exit_with_error(err: anyhow::Error, input_filename: &str, src: &str) -> ! {
+    match err.downcast_ref::<ast::Diagnostic>() {
+        Some(spanned) => {
+            let (line, col) = ast::LineIndex::new(src).line_col(spanned.span.lo);
+            println!("error: {}:{}:{}: {}", input_filename, line, col, spanned.msg);
+        }
+        None => println!("error: {}", err),
+    }
+    std::process::exit(1);
+}
+
+// warnings go to stderr so they don't end up mixed into the program's output.
+// with -Werror they're printed as errors instead, and compilation stops
+fn print_warnings(
+    mut warnings: Vec<ast::Diagnostic>,
+    lint_levels: &lint::LintLevels,
+    input_filename: &str,
+    src: &str,
+) {
+    let lines = ast::LineIndex::new(src);
+    warnings.retain(|warning| lint_levels.enabled(warning.lint.unwrap_or("")));
+    warnings.sort_by_key(|warning| warning.span.lo);
+    for warning in warnings.iter() {
+        let (line, col) = lines.line_col(warning.span.lo);
+        if lint_levels.werror {
+            println!("error: {}:{}:{}: {}", input_filename, line, col, warning.msg);
+        } else {
+            eprintln!(
+                "warning: {}:{}:{}: {} [{}]",
+                input_filename,
+                line,
+                col,
+                warning.msg,
+                warning.lint.unwrap_or("")
+            );
+        }
+    }
+    if lint_levels.werror && !warnings.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// read, parse, typecheck, lint and fold the input file and every file it imports, exiting on
+// any error. the input file comes last, and has to define run if needs_run is set
+fn load_prog(
+    input_filename: &str,
+    lint_levels: &lint::LintLevels,
+    needs_run: bool,
+) -> Vec<modules::Module<typecheck::TCProg>> {
+    let parsed = modules::load(input_filename).unwrap_or_else(|msg| {
+        println!("error: {}", msg);
+        std::process::exit(1);
+    });
+
+    let mut typed = vec![];
+    let count = parsed.len();
+    for (i, module) in parsed.into_iter().enumerate() {
+        let (filename, src) = (&module.filename, &module.src);
+        let mut warnings = vec![];
+        // only the input file has to define run
+        let typed_prog = if needs_run && i == count - 1 {
+            typecheck::typecheck_with_warnings(module.prog, &mut warnings)
+        } else {
+            typecheck::typecheck_module(module.prog, &mut warnings)
+        }
+        .unwrap_or_else(|err| exit_with_error(err, filename, src));
+        lint::lint_prog(&typed_prog, &mut warnings);
+        print_warnings(warnings, lint_levels, filename, src);
+
+        let typed_prog =
+            fold::fold_prog(typed_prog).unwrap_or_else(|err| exit_with_error(err, filename, src));
+        typed.push(modules::Module {
+            filename: module.filename,
+            name: module.name,
+            src: module.src,
+            prog: typed_prog,
+        });
+    }
+    typed
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::kaleidoscope::ProgParser;
-    use crate::typecheck::typecheck;
+    use ekcc::ast::{BOp, Diagnostic, LineIndex, Lit, Span};
+    use ekcc::bench::{default_csv_path, summarize, Summary};
+    use ekcc::dylib;
+    use ekcc::fold::{fold_binop, fold_prog};
+    use ekcc::host::{HostFns, Value};
+    use ekcc::lexer::{self, Tok};
+    use ekcc::lint::lint_prog;
+    use ekcc::lsp::{self, Position};
+    use ekcc::modules;
+    use ekcc::parse::{parse, parse_recovering};
+    use ekcc::typecheck::{typecheck, typecheck_module, TCAtomType, TCExp, TCStmt, TCType};
     use std::fs::read_to_string;
 
     fn test_file(filename: &str) {
         let file_contents_str = read_to_string(filename).unwrap();
-        let prog = ProgParser::new().parse(&file_contents_str).unwrap();
+        let prog = parse(&file_contents_str).unwrap();
         let typed_prog = typecheck(prog).unwrap();
         println!("typechecked AST: {:#?}", typed_prog);
         unimplemented!("test jit");
     }
+
+    // at is the line and column the error points at, for errors that have a location
+    fn test_file_fails_typecheck(filename: &str, msg: &str, at: Option<(u32, u32)>) {
+        let file_contents_str = read_to_string(filename).unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        let err = typecheck(prog).unwrap_err();
+        assert!(err.to_string().contains(msg), "{}: {}", filename, err);
+        if let Some(at) = at {
+            let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+            let lines = LineIndex::new(&file_contents_str);
+            assert_eq!(lines.line_col(diagnostic.span.lo), at, "{}: {}", filename, err);
+        }
+    }
     #[test]
     fn can_parse_serialize_typecheck_test1() {
         test_file("test/test1.ek");
@@ -131,4 +451,341 @@ mod tests {
     fn can_parse_serialize_typecheck_test2() {
         test_file("test/test2.ek");
     }
+    #[test]
+    fn typechecks_forward_and_mutually_recursive_calls() {
+        let file_contents_str = read_to_string("test/test32_mutualrecursion.ek").unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        assert!(typecheck(prog).is_ok());
+    }
+    #[test]
+    fn loads_each_imported_module_once_before_its_importers() {
+        let modules = modules::load("test/modules/main.ek").unwrap();
+        let names: Vec<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["mathlib", "geometry", "main"]);
+        // both modules have a private helper, they can't collide once qualified
+        assert!(modules[0].prog.funcs.iter().any(|f| f.globid == "mathlib::helper"));
+        assert!(modules[1].prog.funcs.iter().any(|f| f.globid == "geometry::helper"));
+    }
+    fn module_error(filename: &str) -> String {
+        match modules::load(filename) {
+            Ok(_) => panic!("{} loaded", filename),
+            Err(err) => err.to_string(),
+        }
+    }
+    #[test]
+    fn rejects_calls_to_functions_that_are_not_pub() {
+        let err = module_error("test/modules/errtest_private.ek");
+        assert!(err.starts_with("test/modules/errtest_private.ek:4:12: "), "{}", err);
+        assert!(err.contains("function helper in module mathlib is not pub"), "{}", err);
+    }
+    #[test]
+    fn rejects_calls_to_modules_that_are_not_imported() {
+        let err = module_error("test/modules/errtest_notimported.ek");
+        assert!(err.starts_with("test/modules/errtest_notimported.ek:4:12: "), "{}", err);
+        assert!(err.contains("module mathlib is not imported"), "{}", err);
+    }
+    #[test]
+    fn rejects_import_cycles() {
+        let err = module_error("test/modules/errtest_cycle_a.ek");
+        assert!(err.starts_with("test/modules/errtest_cycle_b.ek:1:1: "), "{}", err);
+        assert!(err.contains("import cycle"), "{}", err);
+    }
+    #[test]
+    fn rejects_imports_of_missing_files() {
+        let err = module_error("test/modules/errtest_missing.ek");
+        assert!(err.starts_with("test/modules/errtest_missing.ek:1:1: "), "{}", err);
+        assert!(err.contains("could not find test/modules/nothere.ek"), "{}", err);
+    }
+    #[test]
+    fn library_without_run_typechecks_as_a_module() {
+        let file_contents_str = read_to_string("test/linking/mathlib.ek").unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        assert!(typecheck_module(prog, &mut vec![]).is_ok());
+        let prog = parse(&file_contents_str).unwrap();
+        assert!(typecheck(prog).is_err());
+    }
+    #[test]
+    fn finds_extern_functions_in_linked_libraries() {
+        let libm = dylib::open(&dylib::lib_filename("m")).unwrap();
+        assert!(dylib::lookup(&[libm], "cos").is_some());
+        assert!(dylib::lookup(&[], "not_a_real_function").is_none());
+    }
+    #[test]
+    fn checks_externs_against_host_function_signatures() {
+        let file_contents_str = read_to_string("test/test34_host.ek").unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        let typed_prog = typecheck(prog).unwrap();
+        let int = TCType::AtomType(TCAtomType::IntType);
+        let float = TCType::AtomType(TCAtomType::FloatType);
+        let mut host = HostFns::with_args(vec!["4".to_string()]);
+        host.register("scale", int, &[float, int], |_| Some(Value::Int(0)))
+            .unwrap();
+        assert!(host.check_externs(&typed_prog.externs).is_err());
+        host.register("scale", float, &[float, int], |_| Some(Value::Float(0.0)))
+            .unwrap();
+        assert!(host.check_externs(&typed_prog.externs).is_ok());
+        let int_ref = TCType::Ref(false, TCAtomType::IntType);
+        assert!(host.register("bad", int_ref, &[], |_| None).is_err());
+    }
+    #[test]
+    fn parses_entry_arguments_as_parameter_types() {
+        let int = TCType::AtomType(TCAtomType::IntType);
+        let bool_ = TCType::AtomType(TCAtomType::BoolType);
+        let float = TCType::AtomType(TCAtomType::FloatType);
+        assert_eq!(Value::parse(int, "-5"), Some(Value::Int(-5)));
+        assert_eq!(Value::parse(float, "2.5"), Some(Value::Float(2.5)));
+        assert_eq!(Value::parse(bool_, "true"), Some(Value::Bool(true)));
+        assert_eq!(Value::parse(int, "2.5"), None);
+        assert_eq!(Value::parse(TCType::Ref(false, TCAtomType::IntType), "1"), None);
+    }
+    #[test]
+    fn answers_hover_and_definition_queries() {
+        let filename = "test/test35_tests.ek";
+        let analysis = lsp::analyze(filename, &read_to_string(filename).unwrap());
+        assert!(analysis.problems.is_empty());
+        assert_eq!(analysis.symbols.len(), 8);
+        // the $n in `return $n;`
+        let pos = Position {
+            line: 4,
+            character: 16,
+        };
+        assert_eq!(analysis.hover(pos).unwrap().1, "int");
+        assert_eq!(analysis.definition(pos).unwrap().range.start.line, 1);
+        let src = "def int run() {\n    assert 1;\n    return 0;\n}\n";
+        let errors = lsp::analyze("test/errtest12_assertnotbool.ek", src);
+        assert_eq!(errors.problems.len(), 1);
+        assert_eq!(errors.problems[0].range.start.line, 1);
+    }
+    #[test]
+    fn rejects_aliasing_noalias_args() {
+        test_file_fails_typecheck(
+            "test/errtest8_noaliasalias.ek",
+            "passes aliasing variables $x and $y",
+            Some((10, 14)),
+        );
+        test_file_fails_typecheck(
+            "test/errtest19_refparamalias.ek",
+            "passes aliasing variables $p and $q",
+            Some((9, 14)),
+        );
+    }
+    #[test]
+    fn typechecks_test_items_and_assertions() {
+        let file_contents_str = read_to_string("test/test35_tests.ek").unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        let typed_prog = typecheck(prog).unwrap();
+        let tests: Vec<&str> = typed_prog
+            .funcs
+            .iter()
+            .filter_map(|func| func.test.as_deref())
+            .collect();
+        assert_eq!(tests.len(), 5);
+        assert_eq!(tests[0], "fib of small numbers");
+    }
+    #[test]
+    fn rejects_assertions_on_non_bools() {
+        test_file_fails_typecheck(
+            "test/errtest12_assertnotbool.ek",
+            "assert needs a bool expression",
+            Some((3, 12)),
+        );
+    }
+    #[test]
+    fn reports_every_syntax_error_and_keeps_parsing() {
+        let file_contents_str = read_to_string("test/errtest13_syntaxerrors.ek").unwrap();
+        let (prog, errors) = parse_recovering(&file_contents_str);
+        let starts: Vec<usize> = errors.iter().map(|e| e.span.lo).collect();
+        assert_eq!(errors.len(), 5);
+        assert!(starts.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(errors[3].msg.contains("99999999999"));
+        assert_eq!(prog.unwrap().funcs.len(), 2);
+        assert!(parse(&read_to_string("test/crashtest1_bigliteral.ek").unwrap()).is_err());
+    }
+    #[test]
+    fn lexes_numbers_strings_and_comments() {
+        let file_contents_str = read_to_string("test/test36_literals.ek").unwrap();
+        let (tokens, errors) = lexer::tokens(&file_contents_str);
+        assert!(errors.is_empty());
+        assert!(tokens[0].1.is_trivia());
+        let literals: Vec<Tok> = tokens
+            .into_iter()
+            .map(|(_, tok, _)| tok)
+            .filter(|tok| matches!(tok, Tok::IntLit(_) | Tok::FloatLit(_) | Tok::StrLit(_)))
+            .collect();
+        assert_eq!(literals[0], Tok::IntLit(255));
+        assert_eq!(literals[1], Tok::IntLit(10));
+        assert_eq!(literals[2], Tok::IntLit(1_000_000));
+        assert_eq!(literals[3], Tok::FloatLit(1.5e-3));
+        assert_eq!(literals[4], Tok::FloatLit(2.5e5));
+        let escaped = "tab\there, \"quoted\"\nand a backslash: \\";
+        assert_eq!(literals[5], Tok::StrLit(escaped.to_string()));
+    }
+    #[test]
+    fn reports_each_lexical_error() {
+        let file_contents_str = read_to_string("test/errtest14_lexerrors.ek").unwrap();
+        let (_, errors) = lexer::tokens(&file_contents_str);
+        let lines = ekcc::ast::LineIndex::new(&file_contents_str);
+        let error_lines: Vec<u32> = errors.iter().map(|e| lines.line_col(e.span.lo).0).collect();
+        assert_eq!(error_lines, vec![2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert!(errors[7].msg.starts_with("unterminated string"));
+        assert_eq!(errors[8].msg, "hex/binary literals can't have a fractional part");
+        assert_eq!(lines.line_col(errors[8].span.lo), (10, 19));
+    }
+    #[test]
+    fn parses_and_types_numeric_literal_edge_cases() {
+        let file_contents_str = read_to_string("test/test37_numeric_literals.ek").unwrap();
+        fold_prog(typecheck(parse(&file_contents_str).unwrap()).unwrap()).unwrap();
+        let prog = |body: &str| parse(&format!("def int run() {{ {} return 0; }}", body));
+        assert!(prog("int $x = -2147483648;").is_ok());
+        assert!(prog("int $x = -2147483649;").is_err());
+        assert!(prog("float $x = 1e400;").is_err());
+        assert!(prog("float $x = 1e-400;").is_err());
+        assert!(prog("float $x = 0e-400;").is_ok());
+        let errors = prog("int $x = 2147483648; float $y = 1e999;").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+    #[test]
+    fn types_int_literals_from_their_context() {
+        let file_contents_str = read_to_string("test/test38_cint_literals.ek").unwrap();
+        let prog = typecheck(parse(&file_contents_str).unwrap()).unwrap();
+        let cint = TCType::AtomType(TCAtomType::CIntType);
+        match &prog.funcs[1].blk.stmts[0] {
+            TCStmt::VDeclStmt { exp, .. } => match &exp.exp {
+                TCExp::BinOp { lhs, rhs, .. } => {
+                    assert_eq!((exp.type_, lhs.type_, rhs.type_), (cint, cint, cint))
+                }
+                other => panic!("expected a BinOp, got {:?}", other),
+            },
+            other => panic!("expected a declaration, got {:?}", other),
+        }
+        test_file_fails_typecheck(
+            "test/errtest15_cintfromint.ek",
+            "variable declaration assigns to wrong type",
+            None,
+        );
+    }
+    #[test]
+    fn typechecks_globals_and_constants() {
+        let file_contents_str = read_to_string("test/test39_globals.ek").unwrap();
+        let prog = typecheck(parse(&file_contents_str).unwrap()).unwrap();
+        let globals: Vec<(bool, &str, &Lit)> = prog
+            .globals
+            .iter()
+            .map(|g| (g.constant, g.vdecl.varid.as_str(), &g.value))
+            .collect();
+        assert_eq!(
+            globals,
+            vec![
+                (true, "HALF", &Lit::LitFloat(0.5)),
+                (true, "LIMIT", &Lit::LitInt(10)),
+                (false, "calls", &Lit::LitInt(0)),
+                (false, "area", &Lit::LitFloat(1.5)),
+                (false, "total", &Lit::LitInt(0)),
+            ]
+        );
+        test_file_fails_typecheck(
+            "test/errtest16_constassign.ek",
+            "passes const $LIMIT to a ref argument",
+            Some((9, 10)),
+        );
+        test_file_fails_typecheck(
+            "test/errtest17_globalinit.ek",
+            "has to be known at compile time",
+            Some((2, 17)),
+        );
+    }
+    #[test]
+    fn typechecks_arrays() {
+        let file_contents_str = read_to_string("test/test40_arrays.ek").unwrap();
+        let prog = typecheck(parse(&file_contents_str).unwrap()).unwrap();
+        let squares = prog.funcs.iter().find(|f| f.globid == "squares").unwrap();
+        assert_eq!(squares.type_, TCType::Array(TCAtomType::IntType));
+        let sum = prog.funcs.iter().find(|f| f.globid == "sum").unwrap();
+        assert_eq!(sum.args[0].type_, TCType::Array(TCAtomType::FloatType));
+        test_file_fails_typecheck(
+            "test/errtest18_printarray.ek",
+            "arrays can't be printed",
+            Some((4, 11)),
+        );
+    }
+    #[test]
+    fn rejects_missing_return() {
+        test_file_fails_typecheck(
+            "test/errtest10_missingreturn.ek",
+            "can reach the end of its body without returning a value",
+            Some((3, 1)),
+        );
+    }
+    #[test]
+    fn rejects_overflow_through_constant_cint_variable() {
+        test_file_fails_typecheck(
+            "test/test22.ek",
+            "cint overflow in constant expression",
+            Some((3, 10)),
+        );
+    }
+    #[test]
+    fn forgets_constants_declared_in_a_block_when_it_ends() {
+        let file_contents_str = read_to_string("test/test41_shadowedconsts.ek").unwrap();
+        assert!(typecheck(parse(&file_contents_str).unwrap()).is_ok());
+    }
+    #[test]
+    fn rejects_cint_division_by_constant_zero() {
+        test_file_fails_typecheck(
+            "test/cint_test_div_zero.ek",
+            "cint division by zero in constant expression",
+            Some((3, 11)),
+        );
+    }
+    #[test]
+    fn warns_on_shadowing_and_unused_variables() {
+        let file_contents_str = read_to_string("test/test7_shadowing.ek").unwrap();
+        let prog = parse(&file_contents_str).unwrap();
+        let mut warnings = vec![];
+        lint_prog(&typecheck(prog).unwrap(), &mut warnings);
+        let lints: Vec<&str> = warnings.iter().map(|w| w.lint.unwrap()).collect();
+        assert!(lints.contains(&"shadowing"));
+        assert_eq!(lints.iter().filter(|l| **l == "unused-variable").count(), 2);
+    }
+    #[test]
+    fn summarizes_bench_samples() {
+        let odd = Summary {
+            median: 3.0,
+            min: 1,
+            stddev: 2.0,
+        };
+        assert_eq!(summarize(&[5, 1, 3]), odd);
+        let even = summarize(&[4, 1, 3, 2]);
+        assert_eq!((even.median, even.min), (2.5, 1));
+        assert!((even.stddev - (5.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        // the sample standard deviation of one sample would divide by zero
+        let one = Summary {
+            median: 7.0,
+            min: 7,
+            stddev: 0.0,
+        };
+        assert_eq!(summarize(&[7]), one);
+    }
+    #[test]
+    fn names_bench_csv_files_like_the_results_directory() {
+        assert_eq!(default_csv_path("test/bigfile-cf.ek"), "results/test_bigfile-cf-aa.csv");
+        assert_eq!(default_csv_path("test20.ek"), "results/test_test20-aa.csv");
+    }
+    #[test]
+    fn rejects_overflowing_cint_constant() {
+        test_file_fails_typecheck(
+            "test/errtest9_cintconstoverflow.ek",
+            "cint overflow in constant expression",
+            Some((3, 15)),
+        );
+        // the typechecker gets there first on anything it has checked, but folding on its own
+        // still refuses to wrap
+        let cint = TCType::AtomType(TCAtomType::CIntType);
+        let max = Lit::LitInt(i32::MAX);
+        let span = Span::default();
+        assert!(fold_binop(&BOp::Add, cint, &max, &Lit::LitInt(1), span).is_err());
+        let int = TCType::AtomType(TCAtomType::IntType);
+        let wrapped = fold_binop(&BOp::Add, int, &max, &Lit::LitInt(1), span).unwrap();
+        assert_eq!(wrapped, Some(Lit::LitInt(i32::MIN)));
+    }
 }
@@ -0,0 +1,299 @@
+use crate::ast::{Block, Exp, ExpKind, Extern, LineIndex, Prog, Span, Stmt, StmtKind};
+use crate::parse::parse;
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+// one source file of the program, parsed (P = Prog) or typechecked (P = TCProg)
+#[derive(Debug, Clone)]
+pub struct Module<P> {
+    pub filename: String,
+    // what importers call it, the file name without .ek
+    pub name: String,
+    pub src: String,
+    pub prog: P,
+}
+
+// parse input_filename and every file it imports, directly or not. imports come before the
+// files that import them, so the input file's module is last.
+//
+// functions in an imported module are renamed module::name, which is also how importers call
+// them. every module::name a file calls gets added to it as an extern, so each file can be
+// typechecked and lifted on its own and the definitions are found again when the llvm modules
+// are linked together
+pub fn load(input_filename: &str) -> Result<Vec<Module<Prog>>> {
+    let mut loader = Loader::default();
+    loader.load(input_filename)?;
+    Ok(loader.modules)
+}
+
+// like load, with src as the input file's text instead of what's on disk, for an editor's
+// unsaved buffer. imports are still read from disk
+pub fn load_with_src(input_filename: &str, src: String) -> Result<Vec<Module<Prog>>> {
+    let mut loader = Loader::default();
+    loader.load_src(input_filename, src)?;
+    Ok(loader.modules)
+}
+
+#[derive(Default)]
+struct Loader {
+    // files whose imports are being loaded, innermost last, for spotting cycles
+    stack: Vec<(PathBuf, String)>,
+    // index into modules for every file loaded so far
+    loaded: HashMap<PathBuf, usize>,
+    // which file each module name belongs to, since two modules with the same name would
+    // have the same function names once linked
+    names: HashMap<String, PathBuf>,
+    modules: Vec<Module<Prog>>,
+}
+
+impl Loader {
+    fn load(&mut self, filename: &str) -> Result<usize> {
+        let src = read_to_string(filename)
+            .map_err(|msg| anyhow!("could not open {}: {}", filename, msg))?;
+        self.load_src(filename, src)
+    }
+
+    fn load_src(&mut self, filename: &str, src: String) -> Result<usize> {
+        let prog = parse(&src).map_err(|errors| {
+            // every syntax error in the file, one per line
+            let errors: Vec<String> = errors
+                .into_iter()
+                .map(|error| located(filename, &src, error.span, error.msg).to_string())
+                .collect();
+            anyhow!("{}", errors.join("\nerror: "))
+        })?;
+        let path = canonical_path(filename);
+        self.stack.push((path.clone(), filename.to_string()));
+
+        let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+        let mut imports = HashMap::new();
+        for import in prog.imports.iter() {
+            let import_filename = dir.join(&import.path).to_string_lossy().to_string();
+            let error = |msg: String| located(filename, &src, import.span, msg);
+            if !Path::new(&import_filename).is_file() {
+                return Err(error(format!("could not find {}", import_filename)));
+            }
+            let import_path = canonical_path(&import_filename);
+            if let Some(pos) = self.stack.iter().position(|(p, _)| *p == import_path) {
+                let mut cycle: Vec<&str> =
+                    self.stack[pos..].iter().map(|(_, f)| f.as_str()).collect();
+                cycle.push(&import_filename);
+                return Err(error(format!("import cycle: {}", cycle.join(" -> "))));
+            }
+
+            let name = module_name(&import_filename);
+            if !is_ident(&name) {
+                return Err(error(format!(
+                    "{} can't be imported, module names have to be valid identifiers",
+                    import_filename
+                )));
+            }
+            match self.names.get(&name) {
+                Some(other) if *other != import_path => {
+                    return Err(error(format!(
+                        "module name {} is already used by {}",
+                        name,
+                        other.display()
+                    )))
+                }
+                _ => {}
+            }
+            self.names.insert(name.clone(), import_path.clone());
+
+            let index = match self.loaded.get(&import_path) {
+                Some(index) => *index,
+                None => self.load(&import_filename)?,
+            };
+            imports.insert(name, index);
+        }
+        self.stack.pop();
+
+        let mut module = Module {
+            filename: filename.to_string(),
+            name: module_name(filename),
+            src,
+            prog,
+        };
+        // the input file keeps its names, so run is still run
+        let is_import = !self.stack.is_empty();
+        resolve(&mut module, &imports, &self.modules, is_import)?;
+        self.modules.push(module);
+        self.loaded.insert(path, self.modules.len() - 1);
+        Ok(self.modules.len() - 1)
+    }
+}
+
+// check the module::name calls in module against what its imports export, and qualify the
+// names of module's own functions if it's an import
+fn resolve(
+    module: &mut Module<Prog>,
+    imports: &HashMap<String, usize>,
+    modules: &Vec<Module<Prog>>,
+    is_import: bool,
+) -> Result<()> {
+    // only the input file's tests are run
+    if is_import {
+        module.prog.funcs.retain(|func| func.test.is_none());
+    }
+    let local_funcs: HashSet<String> = module.prog.funcs.iter().map(|f| f.globid.clone()).collect();
+    let mut imported_externs = vec![];
+    let mut seen = HashSet::new();
+
+    let (filename, src, module_name) = (&module.filename, &module.src, &module.name);
+    let mut calls = vec![];
+    for func in module.prog.funcs.iter_mut() {
+        calls_in_block(&mut func.blk, &mut calls);
+    }
+    for (globid, span) in calls {
+        match globid.find("::") {
+            Some(sep) => {
+                let (name, func_name) = (&globid[..sep], &globid[sep + 2..]);
+                let error = |msg: String| located(filename, src, span, msg);
+                let imported = match imports.get(name) {
+                    Some(index) => &modules[*index],
+                    None => return Err(error(format!("module {} is not imported", name))),
+                };
+                let func = match imported.prog.funcs.iter().find(|f| f.globid == *globid) {
+                    Some(func) => func,
+                    None => {
+                        return Err(error(format!(
+                            "module {} has no function {}",
+                            name, func_name
+                        )))
+                    }
+                };
+                if !func.public {
+                    return Err(error(format!(
+                        "function {} in module {} is not pub",
+                        func_name, name
+                    )));
+                }
+                if seen.insert(globid.clone()) {
+                    imported_externs.push(Extern {
+                        type_: func.type_.clone(),
+                        globid: globid.clone(),
+                        args: func
+                            .args
+                            .as_ref()
+                            .map(|args| args.iter().map(|a| a.type_.clone()).collect()),
+                        span,
+                    });
+                }
+            }
+            None => {
+                if is_import && local_funcs.contains(globid.as_str()) {
+                    *globid = format!("{}::{}", module_name, globid);
+                }
+            }
+        }
+    }
+
+    if is_import {
+        for func in module.prog.funcs.iter_mut() {
+            func.globid = format!("{}::{}", module.name, func.globid);
+        }
+    }
+    imported_externs.extend(module.prog.externs.drain(..));
+    module.prog.externs = imported_externs;
+    Ok(())
+}
+
+// every function call in the block, with the callee's name ready to be rewritten
+fn calls_in_block<'a>(blk: &'a mut Block, calls: &mut Vec<(&'a mut String, Span)>) {
+    for stmt in blk.stmts.iter_mut().flatten() {
+        calls_in_stmt(stmt, calls);
+    }
+}
+
+fn calls_in_stmt<'a>(stmt: &'a mut Stmt, calls: &mut Vec<(&'a mut String, Span)>) {
+    match &mut stmt.kind {
+        StmtKind::Blk(blk) => calls_in_block(blk, calls),
+        StmtKind::ReturnStmt(exp) => {
+            if let Some(exp) = exp {
+                calls_in_exp(exp, calls);
+            }
+        }
+        StmtKind::VDeclStmt { exp, .. }
+        | StmtKind::ExpStmt(exp)
+        | StmtKind::PrintStmt(exp)
+        | StmtKind::Assert(exp) => calls_in_exp(exp, calls),
+        StmtKind::AssertEq { lhs, rhs } => {
+            calls_in_exp(lhs, calls);
+            calls_in_exp(rhs, calls);
+        }
+        StmtKind::WhileStmt { cond, stmt } => {
+            calls_in_exp(cond, calls);
+            calls_in_stmt(stmt, calls);
+        }
+        StmtKind::IfStmt {
+            cond,
+            stmt,
+            else_stmt,
+        } => {
+            calls_in_exp(cond, calls);
+            calls_in_stmt(stmt, calls);
+            if let Some(else_stmt) = else_stmt {
+                calls_in_stmt(else_stmt, calls);
+            }
+        }
+        StmtKind::PrintStmtSlit(_) | StmtKind::Error => {}
+    }
+}
+
+fn calls_in_exp<'a>(exp: &'a mut Exp, calls: &mut Vec<(&'a mut String, Span)>) {
+    let span = exp.span;
+    match &mut exp.kind {
+        ExpKind::Assign { exp, .. } | ExpKind::Cast { exp, .. } | ExpKind::UnaryOp { exp, .. } => {
+            calls_in_exp(exp, calls)
+        }
+        ExpKind::BinOp { lhs, rhs, .. } => {
+            calls_in_exp(lhs, calls);
+            calls_in_exp(rhs, calls);
+        }
+        ExpKind::FuncCall { globid, exps } => {
+            calls.push((globid, span));
+            for exp in exps.iter_mut().flatten() {
+                calls_in_exp(exp, calls);
+            }
+        }
+        ExpKind::NewArray { len: exp, .. }
+        | ExpKind::Len(exp)
+        | ExpKind::Index { index: exp, .. } => calls_in_exp(exp, calls),
+        ExpKind::AssignIndex { index, exp, .. } => {
+            calls_in_exp(index, calls);
+            calls_in_exp(exp, calls);
+        }
+        ExpKind::Literal(_) | ExpKind::VarVal(_) | ExpKind::Error => {}
+    }
+}
+
+// an error pointing into filename, as file:line:col
+fn located(filename: &str, src: &str, span: Span, msg: String) -> anyhow::Error {
+    let (line, col) = LineIndex::new(src).line_col(span.lo);
+    anyhow!("{}:{}:{}: {}", filename, line, col, msg)
+}
+
+// the same file imported through two different relative paths is still one module
+fn canonical_path(filename: &str) -> PathBuf {
+    std::fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename))
+}
+
+fn module_name(filename: &str) -> String {
+    Path::new(filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename)
+        .to_string()
+}
+
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
@@ -1,103 +1,405 @@
+use anyhow::{anyhow, Result};
 use inkwell::passes::{PassManager, PassManagerBuilder};
 use inkwell::module::Module;
 use inkwell::OptimizationLevel;
+use std::str::FromStr;
+use stopwatch::Stopwatch;
 
-// flags indicating which optimization passes to perform
-// names of each field correspond to snake-cased names of passes from the inkwell module
-// arguments are in the order that they are performed in run_pipeline
-// note: default for bools is false, so a default OFlag turns every flag off
-#[derive(Debug, Default, PartialEq)]
-pub struct OFlags {
-    // function fuckery eeeuuuugh
-    pub basic_alias_analysis: bool,
-    pub argument_promotion: bool,
-    pub function_inlining: bool,
-
-    // control flow/dead code
-    pub cfg_simplification: bool,
-    pub aggressive_dce: bool,
-    pub strip_dead_prototypes: bool,
-
-    // loop optimizations
-    pub ind_var_simplify: bool,
-    pub loop_vectorize: bool,
-
-    // constant shit idk
-    pub reassociate: bool,
-    pub sccp: bool,
-
-    // weird asm
-    pub instruction_combining: bool,
-    pub promote_memory_to_register: bool,
-
-    // cleanup
-    pub dead_arg_elimination: bool,
-}
-
-// perform the pipeline specified by oflags on the given module
-pub fn run_pipeline(module: &Module, oflags: OFlags) {
-    // ??????????????
-    let pm = PassManager::create(());
-
-    if oflags.basic_alias_analysis {
-        pm.add_basic_alias_analysis_pass();
-    }
-    if oflags.argument_promotion {
-        pm.add_argument_promotion_pass();
-    }
-    if oflags.function_inlining {
-        pm.add_function_inlining_pass();
-    }
-    if oflags.cfg_simplification {
-        pm.add_cfg_simplification_pass();
-    }
-    if oflags.aggressive_dce {
-        pm.add_aggressive_dce_pass();
-    }
-    if oflags.strip_dead_prototypes {
-        pm.add_strip_dead_prototypes_pass();
+// the standard pipelines, as selected by -O0 through -O3 and -Os
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl FromStr for OptLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            "3" => Ok(OptLevel::O3),
+            "s" => Ok(OptLevel::Os),
+            _ => Err(anyhow!("unknown optimization level: -O{}", s)),
+        }
     }
-    if oflags.ind_var_simplify {
-        pm.add_ind_var_simplify_pass();
+}
+
+// a single llvm pass that can be requested by name with --passes
+pub struct PassInfo {
+    pub name: &'static str,
+    add: for<'ctx> fn(&PassManager<Module<'ctx>>),
+}
+
+impl PassInfo {
+    pub fn add_to(&self, pm: &PassManager<Module>) {
+        (self.add)(pm)
     }
-    if oflags.loop_vectorize {
-        pm.add_loop_vectorize_pass();
+
+    pub fn is_alias_analysis(&self) -> bool {
+        ["basicaa", "tbaa", "scoped-noalias"].contains(&self.name)
     }
-    if oflags.reassociate {
-        pm.add_reassociate_pass();
+}
+
+// names follow llvm's own (the ones `opt` accepts), so pipelines can be copied from llvm docs
+pub static PASSES: &[PassInfo] = &[
+    // interprocedural
+    PassInfo { name: "argpromotion", add: |pm| pm.add_argument_promotion_pass() },
+    PassInfo { name: "constmerge", add: |pm| pm.add_constant_merge_pass() },
+    PassInfo { name: "deadargelim", add: |pm| pm.add_dead_arg_elimination_pass() },
+    PassInfo { name: "functionattrs", add: |pm| pm.add_function_attrs_pass() },
+    PassInfo { name: "inline", add: |pm| pm.add_function_inlining_pass() },
+    PassInfo { name: "always-inline", add: |pm| pm.add_always_inliner_pass() },
+    PassInfo { name: "globaldce", add: |pm| pm.add_global_dce_pass() },
+    PassInfo { name: "globalopt", add: |pm| pm.add_global_optimizer_pass() },
+    PassInfo { name: "ipconstprop", add: |pm| pm.add_ip_constant_propagation_pass() },
+    PassInfo { name: "prune-eh", add: |pm| pm.add_prune_eh_pass() },
+    PassInfo { name: "ipsccp", add: |pm| pm.add_ipsccp_pass() },
+    PassInfo { name: "strip-dead-prototypes", add: |pm| pm.add_strip_dead_prototypes_pass() },
+    PassInfo { name: "strip", add: |pm| pm.add_strip_symbol_pass() },
+    // vectorization
+    PassInfo { name: "loop-vectorize", add: |pm| pm.add_loop_vectorize_pass() },
+    PassInfo { name: "slp-vectorizer", add: |pm| pm.add_slp_vectorize_pass() },
+    // scalar
+    PassInfo { name: "adce", add: |pm| pm.add_aggressive_dce_pass() },
+    PassInfo { name: "bdce", add: |pm| pm.add_bit_tracking_dce_pass() },
+    PassInfo { name: "alignment-from-assumptions", add: |pm| pm.add_alignment_from_assumptions_pass() },
+    PassInfo { name: "simplifycfg", add: |pm| pm.add_cfg_simplification_pass() },
+    PassInfo { name: "dse", add: |pm| pm.add_dead_store_elimination_pass() },
+    PassInfo { name: "scalarizer", add: |pm| pm.add_scalarizer_pass() },
+    PassInfo { name: "mldst-motion", add: |pm| pm.add_merged_load_store_motion_pass() },
+    PassInfo { name: "gvn", add: |pm| pm.add_gvn_pass() },
+    PassInfo { name: "newgvn", add: |pm| pm.add_new_gvn_pass() },
+    PassInfo { name: "indvars", add: |pm| pm.add_ind_var_simplify_pass() },
+    PassInfo { name: "instcombine", add: |pm| pm.add_instruction_combining_pass() },
+    PassInfo { name: "jump-threading", add: |pm| pm.add_jump_threading_pass() },
+    PassInfo { name: "licm", add: |pm| pm.add_licm_pass() },
+    PassInfo { name: "loop-deletion", add: |pm| pm.add_loop_deletion_pass() },
+    PassInfo { name: "loop-idiom", add: |pm| pm.add_loop_idiom_pass() },
+    PassInfo { name: "loop-rotate", add: |pm| pm.add_loop_rotate_pass() },
+    PassInfo { name: "loop-reroll", add: |pm| pm.add_loop_reroll_pass() },
+    PassInfo { name: "loop-unroll", add: |pm| pm.add_loop_unroll_pass() },
+    PassInfo { name: "loop-unswitch", add: |pm| pm.add_loop_unswitch_pass() },
+    PassInfo { name: "memcpyopt", add: |pm| pm.add_memcpy_optimize_pass() },
+    PassInfo { name: "partially-inline-libcalls", add: |pm| pm.add_partially_inline_lib_calls_pass() },
+    PassInfo { name: "lowerswitch", add: |pm| pm.add_lower_switch_pass() },
+    PassInfo { name: "mem2reg", add: |pm| pm.add_promote_memory_to_register_pass() },
+    PassInfo { name: "reg2mem", add: |pm| pm.add_demote_memory_to_register_pass() },
+    PassInfo { name: "reassociate", add: |pm| pm.add_reassociate_pass() },
+    PassInfo { name: "sccp", add: |pm| pm.add_sccp_pass() },
+    PassInfo { name: "sroa", add: |pm| pm.add_scalar_repl_aggregates_pass() },
+    PassInfo { name: "tailcallelim", add: |pm| pm.add_tail_call_elimination_pass() },
+    PassInfo { name: "constprop", add: |pm| pm.add_constant_propagation_pass() },
+    PassInfo { name: "correlated-propagation", add: |pm| pm.add_correlated_value_propagation_pass() },
+    PassInfo { name: "early-cse", add: |pm| pm.add_early_cse_pass() },
+    PassInfo { name: "lower-expect", add: |pm| pm.add_lower_expect_intrinsic_pass() },
+    PassInfo { name: "verify", add: |pm| pm.add_verifier_pass() },
+    // alias analysis
+    PassInfo { name: "basicaa", add: |pm| pm.add_basic_alias_analysis_pass() },
+    PassInfo { name: "tbaa", add: |pm| pm.add_type_based_alias_analysis_pass() },
+    PassInfo { name: "scoped-noalias", add: |pm| pm.add_scoped_no_alias_aa_pass() },
+];
+
+// the snake_case names -f has always taken, mapped onto the llvm names above
+static FLAG_ALIASES: &[(&str, &str)] = &[
+    ("argument_promotion", "argpromotion"),
+    ("basic_alias_analysis", "basicaa"),
+    ("function_inlining", "inline"),
+    ("cfg_simplification", "simplifycfg"),
+    ("aggressive_dce", "adce"),
+    ("strip_dead_prototypes", "strip-dead-prototypes"),
+    ("ind_var_simplify", "indvars"),
+    ("loop_vectorize", "loop-vectorize"),
+    ("reassociate", "reassociate"),
+    ("sccp", "sccp"),
+    ("instruction_combining", "instcombine"),
+    ("promote_memory_to_register", "mem2reg"),
+    ("dead_arg_elimination", "deadargelim"),
+];
+
+pub fn lookup_pass(name: &str) -> Option<&'static PassInfo> {
+    let name = FLAG_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, llvm_name)| llvm_name);
+    PASSES.iter().find(|pass| pass.name == name)
+}
+
+// parse a --passes string like "mem2reg,instcombine,gvn", keeping the order given
+pub fn parse_pass_list(passes: &str) -> Result<Vec<&'static PassInfo>> {
+    passes
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| lookup_pass(name).ok_or(anyhow!("unknown pass: {}", name)))
+        .collect()
+}
+
+// how many times run_pipeline goes over the pass list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    Times(u32),
+    // keep going until a whole run leaves the IR untouched
+    FixedPoint,
+}
+
+// give up on --fixed-point after this many runs, some pass combinations never settle
+const MAX_FIXED_POINT_RUNS: u32 = 100;
+
+// the passes requested with -f or --passes, in the order they were given
+pub struct Pipeline {
+    pub passes: Vec<&'static PassInfo>,
+    pub repeat: Repeat,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            passes: vec![],
+            repeat: Repeat::Times(1),
+        }
     }
-    if oflags.sccp {
-        pm.add_sccp_pass();
+}
+
+// size of the IR at some point in the pipeline, for --time-passes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IrStats {
+    pub functions: usize,
+    pub blocks: usize,
+    pub instructions: usize,
+}
+
+// only counts functions with bodies, declarations (externs, print helpers) don't change
+pub fn ir_stats(module: &Module) -> IrStats {
+    let mut stats = IrStats::default();
+    let mut maybe_cur_fn = module.get_first_function();
+    while let Some(cur_fn) = maybe_cur_fn {
+        let mut maybe_bb = cur_fn.get_first_basic_block();
+        if maybe_bb.is_some() {
+            stats.functions += 1;
+        }
+        while let Some(bb) = maybe_bb {
+            stats.blocks += 1;
+            let mut maybe_instr = bb.get_first_instruction();
+            while let Some(instr) = maybe_instr {
+                stats.instructions += 1;
+                maybe_instr = instr.get_next_instruction();
+            }
+            maybe_bb = bb.get_next_basic_block();
+        }
+        maybe_cur_fn = cur_fn.get_next_function();
     }
-    if oflags.instruction_combining {
-        pm.add_instruction_combining_pass();
+    stats
+}
+
+// one row of the --time-passes report
+#[derive(Debug)]
+pub struct PassTiming {
+    pub pass: String,
+    // which run of the pass list this was, starting at 1 (see Repeat)
+    pub run: u32,
+    pub nanos: u128,
+    pub before: IrStats,
+    pub after: IrStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimingFormat {
+    Table,
+    Csv,
+}
+
+pub fn format_timings(timings: &[PassTiming], format: TimingFormat) -> String {
+    let mut out = String::new();
+    match format {
+        TimingFormat::Table => {
+            out.push_str(&format!(
+                "{:<28} {:>4} {:>14} {:>22} {:>22}\n",
+                "pass", "run", "time (ns)", "fns/blocks/insts before", "fns/blocks/insts after"
+            ));
+            for t in timings.iter() {
+                out.push_str(&format!(
+                    "{:<28} {:>4} {:>14} {:>22} {:>22}\n",
+                    t.pass,
+                    t.run,
+                    t.nanos,
+                    format!("{}/{}/{}", t.before.functions, t.before.blocks, t.before.instructions),
+                    format!("{}/{}/{}", t.after.functions, t.after.blocks, t.after.instructions),
+                ));
+            }
+            let total: u128 = timings.iter().map(|t| t.nanos).sum();
+            out.push_str(&format!("{:<28} {:>4} {:>14}\n", "total", "", total));
+        }
+        TimingFormat::Csv => {
+            out.push_str("pass,run,time (ns),functions before,blocks before,instructions before,functions after,blocks after,instructions after\n");
+            for t in timings.iter() {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    t.pass,
+                    t.run,
+                    t.nanos,
+                    t.before.functions,
+                    t.before.blocks,
+                    t.before.instructions,
+                    t.after.functions,
+                    t.after.blocks,
+                    t.after.instructions,
+                ));
+            }
+        }
     }
-    if oflags.promote_memory_to_register {
-        pm.add_promote_memory_to_register_pass();
+    out
+}
+
+// run f on the module, recording a PassTiming for it if timings is given
+fn timed<F: FnOnce() -> bool>(
+    module: &Module,
+    pass: &str,
+    run: u32,
+    timings: &mut Option<&mut Vec<PassTiming>>,
+    f: F,
+) -> bool {
+    match timings {
+        Some(timings) => {
+            let before = ir_stats(module);
+            let sw = Stopwatch::start_new();
+            let changed = f();
+            let nanos = sw.elapsed().as_nanos();
+            timings.push(PassTiming {
+                pass: pass.to_string(),
+                run,
+                nanos,
+                before,
+                after: ir_stats(module),
+            });
+            changed
+        }
+        None => f(),
     }
-    if oflags.dead_arg_elimination {
-        pm.add_dead_arg_elimination_pass();
+}
+
+// run the passes in pipeline over the module, in order, as many times as it asks for.
+// returns how many times the pass list actually ran
+pub fn run_pipeline(
+    module: &Module,
+    pipeline: &Pipeline,
+    mut timings: Option<&mut Vec<PassTiming>>,
+) -> u32 {
+    if pipeline.passes.is_empty() {
+        return 0;
     }
 
-    // ??????????????????????????????????????
-    pm.run_on(module);
-    pm.run_on(module);
+    // when timing, each pass gets a manager of its own so it can be measured alone.
+    // alias analyses don't transform anything, they only inform the passes that share their
+    // manager, so every one of those managers gets all of the requested analyses. this isn't
+    // quite the untimed pipeline: one shared manager runs consecutive function passes together
+    // function by function, while separate ones each go over the whole module in turn
+    let managers: Vec<(&str, PassManager<Module>)> = if timings.is_some() {
+        let alias_analyses: Vec<&&PassInfo> = pipeline
+            .passes
+            .iter()
+            .filter(|pass| pass.is_alias_analysis())
+            .collect();
+        pipeline
+            .passes
+            .iter()
+            .filter(|pass| !pass.is_alias_analysis())
+            .map(|pass| {
+                let pm = PassManager::create(());
+                for aa in alias_analyses.iter() {
+                    aa.add_to(&pm);
+                }
+                pass.add_to(&pm);
+                (pass.name, pm)
+            })
+            .collect()
+    } else {
+        let pm = PassManager::create(());
+        for pass in pipeline.passes.iter() {
+            pass.add_to(&pm);
+        }
+        vec![("pipeline", pm)]
+    };
+
+    let mut run_once = |run: u32| {
+        let mut changed = false;
+        for (name, pm) in managers.iter() {
+            changed |= timed(module, name, run, &mut timings, || pm.run_on(module));
+        }
+        changed
+    };
+
+    match pipeline.repeat {
+        Repeat::Times(n) => {
+            for run in 1..=n {
+                run_once(run);
+            }
+            n
+        }
+        Repeat::FixedPoint => {
+            // passes are allowed to claim they changed something when they didn't,
+            // so compare the actual IR too
+            let mut runs = 0;
+            let mut ir = module.print_to_string().to_string();
+            while runs < MAX_FIXED_POINT_RUNS {
+                runs += 1;
+                let changed = run_once(runs);
+                let new_ir = module.print_to_string().to_string();
+                if !changed || new_ir == ir {
+                    break;
+                }
+                ir = new_ir;
+            }
+            runs
+        }
+    }
 }
 
-// run the default pipeline on each function
-pub fn run_default_pipeline(module: &Module) {
+// run llvm's standard pipeline for the given level: function passes on each function,
+// then the module passes (inlining, ipo, etc.) on the whole module
+pub fn run_default_pipeline(
+    module: &Module,
+    level: OptLevel,
+    mut timings: Option<&mut Vec<PassTiming>>,
+) {
+    // inliner thresholds are the ones clang uses for each level
+    let (name, opt_level, size_level, inline_threshold) = match level {
+        OptLevel::O0 => return,
+        OptLevel::O1 => ("-O1", OptimizationLevel::Less, 0, None),
+        OptLevel::O2 => ("-O2", OptimizationLevel::Default, 0, Some(225)),
+        OptLevel::O3 => ("-O3", OptimizationLevel::Aggressive, 0, Some(275)),
+        OptLevel::Os => ("-Os", OptimizationLevel::Default, 1, Some(75)),
+    };
     let pass_manager_builder = PassManagerBuilder::create();
-    pass_manager_builder.set_optimization_level(OptimizationLevel::Aggressive);
+    pass_manager_builder.set_optimization_level(opt_level);
+    pass_manager_builder.set_size_level(size_level);
+    if let Some(threshold) = inline_threshold {
+        pass_manager_builder.set_inliner_with_threshold(threshold);
+    }
 
-    let fpm = PassManager::create(module);
-    pass_manager_builder.populate_function_pass_manager(&fpm);
-    let mut maybe_cur_fn = module.get_first_function();
-    loop {
-        if let Some(cur_fn) = maybe_cur_fn {
-            fpm.run_on(&cur_fn);
-            maybe_cur_fn = cur_fn.get_next_function();
-        } else {
-            break;
+    // the builder doesn't expose the individual passes, so the whole level is timed as one
+    timed(module, name, 1, &mut timings, || {
+        let fpm = PassManager::create(module);
+        pass_manager_builder.populate_function_pass_manager(&fpm);
+        fpm.initialize();
+        let mut maybe_cur_fn = module.get_first_function();
+        loop {
+            if let Some(cur_fn) = maybe_cur_fn {
+                fpm.run_on(&cur_fn);
+                maybe_cur_fn = cur_fn.get_next_function();
+            } else {
+                break;
+            }
         }
-    }
+        fpm.finalize();
+
+        let mpm = PassManager::create(());
+        pass_manager_builder.populate_module_pass_manager(&mpm);
+        mpm.run_on(module)
+    });
 }
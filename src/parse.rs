@@ -0,0 +1,132 @@
+use crate::ast::{Diagnostic, Exp, ExpKind, Lit, Prog, Span};
+use crate::kaleidoscope::ProgParser;
+use crate::lexer::{self, Tok};
+use lalrpop_util::{ErrorRecovery, ParseError};
+use std::convert::TryFrom;
+
+// the parser doesn't stop at the first syntax error. it skips ahead to the next ";" in a
+// block, or the next "}" at the top level, leaves a StmtKind::Error where it skipped, and
+// carries on, so every syntax error in a file can be reported at once
+
+// the whole program, or the lexical and syntax errors in it, in order
+pub fn parse(src: &str) -> Result<Prog, Vec<Diagnostic>> {
+    match parse_recovering(src) {
+        (Some(prog), errors) if errors.is_empty() => Ok(prog),
+        (_, errors) => Err(errors),
+    }
+}
+
+// as much of the program as could be parsed, with error nodes where there were syntax
+// errors, and those errors. there's no program if the parser couldn't recover, e.g. at the end
+// of the file
+pub fn parse_recovering(src: &str) -> (Option<Prog>, Vec<Diagnostic>) {
+    let (tokens, mut errors) = lexer::tokens(src);
+    let tokens = tokens
+        .into_iter()
+        .filter(|(_, tok, _)| !tok.is_trivia())
+        .map(Ok);
+    let mut recovered = vec![];
+    let result = ProgParser::new().parse(src, &mut recovered, tokens);
+    errors.extend(
+        recovered
+            .into_iter()
+            .map(|recovery| diagnostic(src, recovery.error)),
+    );
+    let prog = match result {
+        Ok(prog) => Some(prog),
+        Err(err) => {
+            errors.push(diagnostic(src, err));
+            None
+        }
+    };
+    errors.sort_by_key(|error| error.span.lo);
+    (prog, errors)
+}
+
+// an int literal, with its "-" if it has one. text is how it's written
+pub(crate) fn int_literal(value: i128, text: &str) -> Result<Lit, String> {
+    i32::try_from(value).map(Lit::LitInt).map_err(|_| {
+        format!(
+            "integer literal {} doesn't fit in an int, which goes from {} to {}",
+            text,
+            i32::MIN,
+            i32::MAX
+        )
+    })
+}
+
+pub(crate) fn float_literal(value: f64, text: &str) -> Result<Lit, String> {
+    let mantissa = text.split(|c| c == 'e' || c == 'E').next().unwrap_or(text);
+    if value.is_infinite() {
+        Err(format!("float literal {} is too big for a float", text))
+    } else if value == 0.0 && mantissa.chars().any(|c| ('1'..='9').contains(&c)) {
+        Err(format!("float literal {} is too small for a float, it would be 0", text))
+    } else {
+        Ok(Lit::LitFloat(value))
+    }
+}
+
+// the literal, or an error node and the error for the parser to report
+pub(crate) fn literal(
+    lit: Result<Lit, String>,
+    lo: usize,
+    hi: usize,
+    errors: &mut Vec<ErrorRecovery<usize, Tok, Diagnostic>>,
+) -> Box<Exp> {
+    match lit {
+        Ok(lit) => Exp::new(ExpKind::Literal(lit), lo, hi),
+        Err(msg) => {
+            errors.push(ErrorRecovery {
+                error: ParseError::User {
+                    error: Diagnostic::new(Span::new(lo, hi), msg),
+                },
+                dropped_tokens: vec![],
+            });
+            Exp::new(ExpKind::Error, lo, hi)
+        }
+    }
+}
+
+fn diagnostic(src: &str, err: ParseError<usize, Tok, Diagnostic>) -> Diagnostic {
+    match err {
+        // the lexer reports its own errors, this is only here for completeness
+        ParseError::InvalidToken { location } => {
+            Diagnostic::new(Span::new(location, location), "unrecognized character")
+        }
+        ParseError::UnrecognizedEOF { location, expected } => Diagnostic::new(
+            Span::new(location, location),
+            format!("unexpected end of file{}", expecting(&expected)),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (lo, _, hi),
+            expected,
+        } => Diagnostic::new(
+            Span::new(lo, hi),
+            format!("unexpected `{}`{}", &src[lo..hi], expecting(&expected)),
+        ),
+        ParseError::ExtraToken { token: (lo, _, hi) } => {
+            Diagnostic::new(Span::new(lo, hi), format!("unexpected `{}`", &src[lo..hi]))
+        }
+        ParseError::User { error } => error,
+    }
+}
+
+fn expecting(expected: &[String]) -> String {
+    let expected: Vec<&str> = expected.iter().map(|terminal| terminal_name(terminal)).collect();
+    match expected.as_slice() {
+        [] => String::new(),
+        [one] => format!(", expected {}", one),
+        _ => format!(", expected one of {}", expected.join(", ")),
+    }
+}
+
+// the tokens with values in them, by what they are
+fn terminal_name(terminal: &str) -> &str {
+    match terminal {
+        "\"Ident\"" => "a name",
+        "\"IntLit\"" => "an integer",
+        "\"FloatLit\"" => "a float",
+        "\"StrLit\"" => "a string",
+        _ => terminal,
+    }
+}
@@ -0,0 +1,64 @@
+use libc::{_exit, fork, waitpid, WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG};
+use std::io::{stdout, Write};
+
+// run each test and report how it went, like cargo test. every test runs in a child process
+// of its own, so a failed assertion or cint overflow (which exit), or a crash, only fails that
+// test and nothing one test does can be seen by the next. true if they all passed
+pub fn run_tests<F: Fn()>(tests: &[(String, F)]) -> bool {
+    println!("running {} tests", tests.len());
+    let mut failed = vec![];
+    for (name, test) in tests {
+        match run_isolated(test) {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(why) => {
+                println!("test {} ... FAILED ({})", name, why);
+                failed.push(name);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        println!();
+        println!("failures:");
+        for name in failed.iter() {
+            println!("    {}", name);
+        }
+    }
+    println!();
+    println!(
+        "test result: {}. {} passed; {} failed",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failed.len(),
+        failed.len()
+    );
+    failed.is_empty()
+}
+
+fn run_isolated<F: Fn()>(test: &F) -> Result<(), String> {
+    // anything still buffered would otherwise be printed by the child as well
+    stdout().flush().ok();
+    match unsafe { fork() } {
+        -1 => Err("could not start a process for it".to_string()),
+        0 => {
+            test();
+            stdout().flush().ok();
+            unsafe { _exit(0) }
+        }
+        pid => {
+            let mut status = 0;
+            if unsafe { waitpid(pid, &mut status, 0) } == -1 {
+                return Err("lost track of its process".to_string());
+            }
+            if WIFEXITED(status) {
+                match WEXITSTATUS(status) {
+                    0 => Ok(()),
+                    code => Err(format!("exited with {}", code)),
+                }
+            } else if WIFSIGNALED(status) {
+                Err(format!("killed by signal {}", WTERMSIG(status)))
+            } else {
+                Err("stopped".to_string())
+            }
+        }
+    }
+}
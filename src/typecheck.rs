@@ -2,27 +2,32 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::ast::*;
-use std::collections::HashMap;
+use crate::fold::{fold_binop, fold_cast, fold_unaryop};
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
+use std::fmt;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCProg {
     pub externs: Vec<TCExtern>,
+    pub globals: Vec<TCGlobal>,
     pub funcs: Vec<TCFunc>,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCExtern {
     pub type_: TCType,
     pub globid: String,
     pub args: Vec<TCType>,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl TryFrom<Extern> for TCExtern {
     type Error = anyhow::Error;
 
     fn try_from(e: Extern) -> Result<Self, Self::Error> {
-        Ok(TCExtern {
+        let tcextern = TCExtern {
             type_: e.type_.try_into()?,
             globid: e.globid,
             args: e
@@ -31,21 +36,103 @@ impl TryFrom<Extern> for TCExtern {
                 .iter()
                 .map(|x| x.clone().try_into())
                 .collect::<Result<Vec<_>>>()?,
-        })
+            span: e.span,
+        };
+        // C can't know how they're laid out or when to free them
+        let is_array = |type_: &TCType| matches!(type_, TCType::Array(_));
+        if is_array(&tcextern.type_) || tcextern.args.iter().any(is_array) {
+            Err(Diagnostic::new(
+                tcextern.span,
+                format!("extern {} can't take or return arrays", tcextern.globid),
+            ))?
+        }
+        Ok(tcextern)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TCGlobal {
+    pub constant: bool,
+    pub vdecl: TCVDecl,
+    // the initializer, worked out at compile time
+    pub value: Lit,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+// a global's initializer can use the consts declared above it, and nothing else
+fn typecheck_global(
+    global: Global,
+    defined_functions: &HashMap<String, (TCType, Vec<TCType>)>,
+    globals: &[TCGlobal],
+    warnings: &mut Vec<Diagnostic>,
+) -> Result<TCGlobal> {
+    let vdecl: TCVDecl = global.vdecl.try_into()?;
+    if let TCType::Ref(_, _) | TCType::Array(_) = vdecl.type_ {
+        Err(Diagnostic::new(
+            global.span,
+            format!("global ${} can't be a ref or an array", vdecl.varid),
+        ))?
     }
+    if globals.iter().any(|g| g.vdecl.varid == vdecl.varid) {
+        Err(Diagnostic::new(
+            global.span,
+            format!("duplicate global definition: ${}", vdecl.varid),
+        ))?
+    }
+
+    let mut defined_vars = HashMap::new();
+    let mut consts = ConstEnv::default();
+    for g in globals.iter().filter(|g| g.constant) {
+        defined_vars.insert(g.vdecl.varid.clone(), g.vdecl.type_);
+        consts.set(&g.vdecl.varid, Some(g.value.clone()));
+    }
+    let exp = typecheck_exp(global.exp, defined_functions, &defined_vars)?;
+    let exp = literal_as(exp, vdecl.type_);
+    if exp.type_ != vdecl.type_ {
+        Err(Diagnostic::new(
+            exp.span,
+            format!(
+                "global ${} is {:?}, but its initial value is {:?}",
+                vdecl.varid, vdecl.type_, exp.type_
+            ),
+        ))?
+    }
+    let value = match check_constants_exp(&exp, &mut consts, warnings)? {
+        Some(value) => value,
+        None => Err(Diagnostic::new(
+            exp.span,
+            format!(
+                "the initial value of global ${} has to be known at compile time",
+                vdecl.varid
+            ),
+        ))?,
+    };
+    Ok(TCGlobal {
+        constant: global.constant,
+        vdecl,
+        value,
+        span: global.span,
+    })
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCFunc {
+    // the name of a `test "name" { ... }` item
+    pub test: Option<String>,
     pub type_: TCType,
     pub globid: String,
     pub args: Vec<TCVDecl>,
     pub blk: TCBlock,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 fn typecheck_fn(
     fun: Func,
     defined_functions: &HashMap<String, (TCType, Vec<TCType>)>,
+    globals: &[TCGlobal],
+    warnings: &mut Vec<Diagnostic>,
 ) -> Result<TCFunc> {
     // A function may not return a ref type.
     if let Type::Ref(_, _) = fun.type_ {
@@ -54,16 +141,22 @@ fn typecheck_fn(
 
     let re_type: TCType = fun.type_.try_into()?;
     let mut new_args = vec![];
-    let mut defined_vars: HashMap<String, TCType> = HashMap::new();
+    // the file's globals are in scope everywhere, and arguments can shadow them
+    let mut defined_vars: HashMap<String, TCType> = globals
+        .iter()
+        .map(|g| (g.vdecl.varid.clone(), g.vdecl.type_))
+        .collect();
     if let Some(args) = fun.args {
         for arg in args.iter() {
             let arg_new_type: TCType = arg.type_.clone().try_into()?;
-            if let Some(_) = defined_vars.insert(arg.varid.clone(), arg_new_type.clone()) {
+            if new_args.iter().any(|a: &TCVDecl| a.varid == arg.varid) {
                 return Err(anyhow!("two function arguments have the same name!"));
             }
+            defined_vars.insert(arg.varid.clone(), arg_new_type.clone());
             new_args.push(TCVDecl {
                 varid: arg.varid.clone(),
                 type_: arg_new_type,
+                span: arg.span,
             });
         }
     }
@@ -79,18 +172,527 @@ fn typecheck_fn(
         defined_vars,
         re_type_opt,
         HashMap::new(),
+        warnings,
     )?;
+
+    // lift_function can only put an unreachable at the end of a non-void function
+    if re_type != TCType::VoidType && falls_through_block(&my_block) {
+        Err(Diagnostic::new(
+            fun.span,
+            format!(
+                "function {} can reach the end of its body without returning a value",
+                fun.globid
+            ),
+        ))?
+    }
+
+    // globals are storage ids 0 to globals.len() - 1. every other argument gets its own
+    // storage as far as the callee can tell, but plain ref arguments all share one
+    let mut var_ids: HashMap<String, usize> = globals
+        .iter()
+        .enumerate()
+        .map(|(id, g)| (g.vdecl.varid.clone(), id))
+        .collect();
+    let mut next_id = may_alias_id(globals) + 1;
+    for arg in new_args.iter() {
+        if let TCType::Ref(false, _) = arg.type_ {
+            var_ids.insert(arg.varid.clone(), may_alias_id(globals));
+        } else {
+            var_ids.insert(arg.varid.clone(), next_id);
+            next_id += 1;
+        }
+    }
+    check_storage_stmts(&my_block.stmts, globals, &mut var_ids, &mut next_id)?;
+
+    // ref arguments might alias each other, and any call might change a global that isn't
+    // const, so nothing is ever known about them
+    let mut consts = ConstEnv::default();
+    for g in globals.iter() {
+        if g.constant {
+            consts.set(&g.vdecl.varid, Some(g.value.clone()));
+        } else {
+            consts.escape(&g.vdecl.varid);
+        }
+    }
+    for arg in new_args.iter() {
+        consts.escaped.remove(&arg.varid);
+        consts.set(&arg.varid, None);
+        if let TCType::Ref(_, _) = arg.type_ {
+            consts.escape(&arg.varid);
+        }
+    }
+    for stmt in my_block.stmts.iter() {
+        check_constants_stmt(stmt, &mut consts, warnings)?;
+    }
+
     Ok(TCFunc {
+        test: fun.test,
         type_: re_type,
         globid: fun.globid,
         args: new_args,
         blk: my_block,
+        span: fun.span,
+    })
+}
+
+// A noalias ref parameter promises LLVM that nothing else in the callee points at the same
+// storage, so a call may not pass one variable (directly or through a ref to it) to a noalias
+// parameter and to any other ref parameter at the same time. Nor can it pass a global to one,
+// since the callee can use the global directly. A const global can't be changed at all, so it
+// can't be assigned, passed to a ref parameter or have a ref declared to it.
+//
+// var_ids maps each variable in scope to a unique id for the storage it names: globals are the
+// ids below globals.len(), plain variables get a fresh id when declared, refs take the id of the
+// variable they were initialized from.
+fn check_storage_stmts(
+    stmts: &Vec<TCStmt>,
+    globals: &[TCGlobal],
+    var_ids: &mut HashMap<String, usize>,
+    next_id: &mut usize,
+) -> Result<()> {
+    for stmt in stmts.iter() {
+        check_storage_stmt(stmt, globals, var_ids, next_id)?;
+    }
+    Ok(())
+}
+
+fn check_storage_stmt(
+    stmt: &TCStmt,
+    globals: &[TCGlobal],
+    var_ids: &mut HashMap<String, usize>,
+    next_id: &mut usize,
+) -> Result<()> {
+    match stmt {
+        TCStmt::Blk(blk) => {
+            check_storage_stmts(&blk.stmts, globals, &mut var_ids.clone(), next_id)
+        }
+        TCStmt::ReturnStmt(exp) => match exp {
+            Some(exp) => check_storage_exp(exp, globals, var_ids),
+            None => Ok(()),
+        },
+        TCStmt::VDeclStmt { vdecl, exp } => {
+            check_storage_exp(exp, globals, var_ids)?;
+            let target_id = match (vdecl.type_, &exp.exp) {
+                (TCType::Ref(_, _), TCExp::VarVal(target)) => var_ids.get(target).cloned(),
+                _ => None,
+            };
+            if let Some(global) = target_id.and_then(|id| const_global(globals, id)) {
+                Err(Diagnostic::new(
+                    vdecl.span,
+                    format!(
+                        "${} can't be a ref to const ${}",
+                        vdecl.varid, global.vdecl.varid
+                    ),
+                ))?
+            }
+            let id = match target_id {
+                Some(id) => id,
+                None => {
+                    *next_id += 1;
+                    *next_id
+                }
+            };
+            var_ids.insert(vdecl.varid.clone(), id);
+            Ok(())
+        }
+        TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => {
+            check_storage_exp(exp, globals, var_ids)
+        }
+        TCStmt::AssertEq { lhs, rhs } => {
+            check_storage_exp(lhs, globals, var_ids)?;
+            check_storage_exp(rhs, globals, var_ids)
+        }
+        TCStmt::WhileStmt { cond, stmt } => {
+            check_storage_exp(cond, globals, var_ids)?;
+            check_storage_stmt(stmt, globals, &mut var_ids.clone(), next_id)
+        }
+        TCStmt::IfStmt {
+            cond,
+            stmt,
+            else_stmt,
+        } => {
+            check_storage_exp(cond, globals, var_ids)?;
+            check_storage_stmt(stmt, globals, &mut var_ids.clone(), next_id)?;
+            if let Some(else_stmt) = else_stmt {
+                check_storage_stmt(else_stmt, globals, &mut var_ids.clone(), next_id)?;
+            }
+            Ok(())
+        }
+        TCStmt::PrintStmtSlit(_) => Ok(()),
+    }
+}
+
+fn check_storage_exp(
+    exp: &TypedExp,
+    globals: &[TCGlobal],
+    var_ids: &HashMap<String, usize>,
+) -> Result<()> {
+    match &exp.exp {
+        TCExp::Assign { varid, exp: value } => {
+            if let Some(global) = var_ids.get(varid).and_then(|id| const_global(globals, *id)) {
+                Err(Diagnostic::new(
+                    exp.span,
+                    format!("can't assign to const ${}", global.vdecl.varid),
+                ))?
+            }
+            check_storage_exp(value, globals, var_ids)
+        }
+        TCExp::Cast { exp, .. }
+        | TCExp::UnaryOp { exp, .. }
+        | TCExp::NewArray { len: exp, .. }
+        | TCExp::Len(exp)
+        | TCExp::Index { index: exp, .. } => check_storage_exp(exp, globals, var_ids),
+        TCExp::BinOp { lhs, rhs, .. }
+        | TCExp::AssignIndex {
+            index: lhs,
+            exp: rhs,
+            ..
+        } => {
+            check_storage_exp(lhs, globals, var_ids)?;
+            check_storage_exp(rhs, globals, var_ids)
+        }
+        TCExp::Literal(_) | TCExp::VarVal(_) => Ok(()),
+        TCExp::FuncCall {
+            globid,
+            exps,
+            expected_args,
+        } => {
+            let mut ref_args: Vec<(bool, &String, usize, Span)> = vec![];
+            for (arg_type, arg) in expected_args.iter().zip(exps.iter()) {
+                check_storage_exp(arg, globals, var_ids)?;
+                if let (TCType::Ref(noalias, _), TCExp::VarVal(varid)) = (arg_type, &arg.exp) {
+                    if let Some(id) = var_ids.get(varid) {
+                        if let Some(global) = const_global(globals, *id) {
+                            Err(Diagnostic::new(
+                                arg.span,
+                                format!(
+                                    "call to {} passes const ${} to a ref argument",
+                                    globid, global.vdecl.varid
+                                ),
+                            ))?
+                        }
+                        if *noalias && *id < globals.len() {
+                            Err(Diagnostic::new(
+                                arg.span,
+                                format!(
+                                    "call to {} passes global ${} to a noalias ref argument",
+                                    globid, globals[*id].vdecl.varid
+                                ),
+                            ))?
+                        }
+                        if *noalias
+                            && *id == may_alias_id(globals)
+                            && globals.iter().any(|g| !g.constant)
+                        {
+                            Err(Diagnostic::new(
+                                arg.span,
+                                format!(
+                                    "call to {} passes ${}, which might point at a global, to a noalias ref argument",
+                                    globid, varid
+                                ),
+                            ))?
+                        }
+                        ref_args.push((*noalias, varid, *id, arg.span));
+                    }
+                }
+            }
+            for (i, (noalias, varid, id, _)) in ref_args.iter().enumerate() {
+                for (other_noalias, other_varid, other_id, other_span) in ref_args[i + 1..].iter() {
+                    let may_alias = id == other_id
+                        || (*id == may_alias_id(globals) && *other_id < globals.len())
+                        || (*other_id == may_alias_id(globals) && *id < globals.len());
+                    if (*noalias || *other_noalias) && may_alias {
+                        Err(Diagnostic::new(
+                            *other_span,
+                            format!(
+                                "call to {} passes aliasing variables ${} and ${} to a noalias ref argument",
+                                globid, varid, other_varid
+                            ),
+                        ))?
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// the storage id of every plain ref argument. the caller could pass the same variable or a
+// global to more than one of them, so they might all be the same storage as each other or
+// as any global
+fn may_alias_id(globals: &[TCGlobal]) -> usize {
+    globals.len()
+}
+
+// the const global with storage id id, if that's what it is
+fn const_global(globals: &[TCGlobal], id: usize) -> Option<&TCGlobal> {
+    globals.get(id).filter(|g| g.constant)
+}
+
+// Values of variables that are known at compile time, so that constant cint expressions that
+// are sure to overflow are caught here instead of by the checks at run time, and int division
+// by a constant zero (undefined behavior in llvm) gets a warning.
+//
+// A variable is known from its declaration with a constant initializer until it's assigned
+// something that isn't constant. Anything a ref could point at is never known, and loops and
+// branches forget whatever they might assign.
+#[derive(Debug, Clone, Default)]
+struct ConstEnv {
+    values: HashMap<String, Lit>,
+    // ref variables, and every variable a ref was taken of
+    escaped: HashSet<String>,
+}
+
+impl ConstEnv {
+    fn set(&mut self, varid: &String, value: Option<Lit>) {
+        match value {
+            Some(lit) if !self.escaped.contains(varid) => {
+                self.values.insert(varid.clone(), lit);
+            }
+            _ => {
+                self.values.remove(varid);
+            }
+        }
+    }
+
+    fn escape(&mut self, varid: &String) {
+        self.values.remove(varid);
+        self.escaped.insert(varid.clone());
+    }
+
+    fn forget(&mut self, varids: &HashSet<String>) {
+        for varid in varids.iter() {
+            self.values.remove(varid);
+        }
+    }
+}
+
+// every variable the statement could change, directly or through a ref
+fn assigned_vars_stmt(stmt: &TCStmt, vars: &mut HashSet<String>) {
+    match stmt {
+        TCStmt::Blk(blk) => {
+            for stmt in blk.stmts.iter() {
+                assigned_vars_stmt(stmt, vars);
+            }
+        }
+        TCStmt::ReturnStmt(exp) => {
+            if let Some(exp) = exp {
+                assigned_vars_exp(exp, vars);
+            }
+        }
+        TCStmt::VDeclStmt { vdecl, exp } => {
+            if let (TCType::Ref(_, _), TCExp::VarVal(target)) = (vdecl.type_, &exp.exp) {
+                vars.insert(target.clone());
+            }
+            assigned_vars_exp(exp, vars);
+        }
+        TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => {
+            assigned_vars_exp(exp, vars)
+        }
+        TCStmt::AssertEq { lhs, rhs } => {
+            assigned_vars_exp(lhs, vars);
+            assigned_vars_exp(rhs, vars);
+        }
+        TCStmt::WhileStmt { cond, stmt } => {
+            assigned_vars_exp(cond, vars);
+            assigned_vars_stmt(stmt, vars);
+        }
+        TCStmt::IfStmt {
+            cond,
+            stmt,
+            else_stmt,
+        } => {
+            assigned_vars_exp(cond, vars);
+            assigned_vars_stmt(stmt, vars);
+            if let Some(else_stmt) = else_stmt {
+                assigned_vars_stmt(else_stmt, vars);
+            }
+        }
+        TCStmt::PrintStmtSlit(_) => {}
+    }
+}
+
+fn assigned_vars_exp(exp: &TypedExp, vars: &mut HashSet<String>) {
+    match &exp.exp {
+        TCExp::Assign { varid, exp } => {
+            vars.insert(varid.clone());
+            assigned_vars_exp(exp, vars);
+        }
+        TCExp::Cast { exp, .. }
+        | TCExp::UnaryOp { exp, .. }
+        | TCExp::NewArray { len: exp, .. }
+        | TCExp::Len(exp)
+        | TCExp::Index { index: exp, .. } => assigned_vars_exp(exp, vars),
+        // what's in an array is never known, so only its index and value matter
+        TCExp::BinOp { lhs, rhs, .. }
+        | TCExp::AssignIndex {
+            index: lhs,
+            exp: rhs,
+            ..
+        } => {
+            assigned_vars_exp(lhs, vars);
+            assigned_vars_exp(rhs, vars);
+        }
+        TCExp::Literal(_) | TCExp::VarVal(_) => {}
+        TCExp::FuncCall {
+            exps,
+            expected_args,
+            ..
+        } => {
+            for (arg_type, arg) in expected_args.iter().zip(exps.iter()) {
+                if let (TCType::Ref(_, _), TCExp::VarVal(varid)) = (arg_type, &arg.exp) {
+                    vars.insert(varid.clone());
+                }
+                assigned_vars_exp(arg, vars);
+            }
+        }
+    }
+}
+
+fn check_constants_stmt(
+    stmt: &TCStmt,
+    consts: &mut ConstEnv,
+    warnings: &mut Vec<Diagnostic>,
+) -> Result<()> {
+    match stmt {
+        TCStmt::Blk(blk) => {
+            // declarations in the block can shadow outer variables, so it gets a copy of its
+            // own. afterwards only the outer variables it could have changed are lost
+            let mut block_consts = consts.clone();
+            for stmt in blk.stmts.iter() {
+                check_constants_stmt(stmt, &mut block_consts, warnings)?;
+            }
+            let mut assigned = HashSet::new();
+            assigned_vars_stmt(stmt, &mut assigned);
+            consts.forget(&assigned);
+        }
+        TCStmt::ReturnStmt(exp) => {
+            if let Some(exp) = exp {
+                check_constants_exp(exp, consts, warnings)?;
+            }
+        }
+        TCStmt::VDeclStmt { vdecl, exp } => {
+            let value = check_constants_exp(exp, consts, warnings)?;
+            match (vdecl.type_, &exp.exp) {
+                (TCType::Ref(_, _), TCExp::VarVal(target)) => {
+                    consts.escape(target);
+                    consts.escape(&vdecl.varid);
+                }
+                _ => {
+                    // the new variable shadows any outer one of the same name, in this
+                    // scope's copy only
+                    consts.escaped.remove(&vdecl.varid);
+                    consts.set(&vdecl.varid, value);
+                }
+            }
+        }
+        TCStmt::ExpStmt(exp) | TCStmt::PrintStmt(exp) | TCStmt::Assert(exp) => {
+            check_constants_exp(exp, consts, warnings)?;
+        }
+        TCStmt::AssertEq { lhs, rhs } => {
+            check_constants_exp(lhs, consts, warnings)?;
+            check_constants_exp(rhs, consts, warnings)?;
+        }
+        TCStmt::WhileStmt { cond, stmt } => {
+            // a later iteration sees whatever an earlier one assigned
+            let mut assigned = HashSet::new();
+            assigned_vars_stmt(stmt, &mut assigned);
+            assigned_vars_exp(cond, &mut assigned);
+            consts.forget(&assigned);
+            check_constants_exp(cond, consts, warnings)?;
+            check_constants_stmt(stmt, &mut consts.clone(), warnings)?;
+        }
+        TCStmt::IfStmt {
+            cond,
+            stmt,
+            else_stmt,
+        } => {
+            check_constants_exp(cond, consts, warnings)?;
+            let mut then_consts = consts.clone();
+            check_constants_stmt(stmt, &mut then_consts, warnings)?;
+            let mut else_consts = consts.clone();
+            if let Some(else_stmt) = else_stmt {
+                check_constants_stmt(else_stmt, &mut else_consts, warnings)?;
+            }
+            // only what both branches agree on is still known
+            then_consts
+                .values
+                .retain(|varid, lit| else_consts.values.get(varid) == Some(lit));
+            then_consts.escaped.extend(else_consts.escaped);
+            *consts = then_consts;
+        }
+        TCStmt::PrintStmtSlit(_) => {}
+    }
+    Ok(())
+}
+
+// returns the value of the expression if it's known at compile time
+fn check_constants_exp(
+    exp: &TypedExp,
+    consts: &mut ConstEnv,
+    warnings: &mut Vec<Diagnostic>,
+) -> Result<Option<Lit>> {
+    Ok(match &exp.exp {
+        TCExp::Assign { varid, exp } => {
+            let value = check_constants_exp(exp, consts, warnings)?;
+            consts.set(varid, value.clone());
+            value
+        }
+        TCExp::Cast { type_, exp: inner } => check_constants_exp(inner, consts, warnings)?
+            .and_then(|lit| fold_cast(*type_, inner.type_, &lit)),
+        TCExp::BinOp { op, lhs, rhs } => {
+            let lhs_value = check_constants_exp(lhs, consts, warnings)?;
+            let rhs_value = check_constants_exp(rhs, consts, warnings)?;
+            if let (BOp::Div, TCType::AtomType(TCAtomType::IntType), Some(Lit::LitInt(0))) =
+                (op, lhs.type_, &rhs_value)
+            {
+                warnings.push(Diagnostic::warning(
+                    "div-by-zero",
+                    exp.span,
+                    "integer division by zero",
+                ));
+            }
+            match (lhs_value, rhs_value) {
+                (Some(l), Some(r)) => fold_binop(op, lhs.type_, &l, &r, exp.span)?,
+                _ => None,
+            }
+        }
+        TCExp::UnaryOp { op, exp: inner } => match check_constants_exp(inner, consts, warnings)? {
+            Some(lit) => fold_unaryop(op, inner.type_, &lit, exp.span)?,
+            None => None,
+        },
+        TCExp::Literal(lit) => Some(lit.clone()),
+        TCExp::VarVal(varid) => consts.values.get(varid).cloned(),
+        TCExp::NewArray { len: exp, .. } | TCExp::Len(exp) | TCExp::Index { index: exp, .. } => {
+            check_constants_exp(exp, consts, warnings)?;
+            None
+        }
+        TCExp::AssignIndex { index, exp, .. } => {
+            check_constants_exp(index, consts, warnings)?;
+            check_constants_exp(exp, consts, warnings)?;
+            None
+        }
+        TCExp::FuncCall {
+            exps,
+            expected_args,
+            ..
+        } => {
+            for (arg_type, arg) in expected_args.iter().zip(exps.iter()) {
+                check_constants_exp(arg, consts, warnings)?;
+                if let (TCType::Ref(_, _), TCExp::VarVal(varid)) = (arg_type, &arg.exp) {
+                    consts.escape(varid);
+                }
+            }
+            None
+        }
     })
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCBlock {
     pub stmts: Vec<TCStmt>,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 fn typecheck_block(
@@ -99,6 +701,7 @@ fn typecheck_block(
     mut defined_vars: HashMap<String, TCType>, // idk what to do with the muts and the &s tbh
     should_return: Option<TCType>,
     mut shadowed_vars: HashMap<String, TCType>,
+    warnings: &mut Vec<Diagnostic>,
 ) -> Result<TCBlock> {
     /*    /*
     All functions must be declared and/or defined before they are used.
@@ -108,20 +711,37 @@ fn typecheck_block(
     Also, for every expression, determine its type (applying the explicit cast rules from the language specification, and making sure Binops are the same type on both sides or a valid cast.  When printing the AST, the type of each expression should be part of the AST nodes for each expression.
      */*/
 
+    let span = blk.span;
     let mut tc_stmts = vec![];
+    let mut reachable = true;
+    let mut warned_unreachable = false;
     if let Some(stmts) = blk.stmts {
         for stmt in stmts {
+            // only warn once, the rest of the block is unreachable for the same reason
+            if !reachable && !warned_unreachable {
+                warnings.push(Diagnostic::warning(
+                    "unreachable-code",
+                    stmt.span,
+                    "unreachable statement",
+                ));
+                warned_unreachable = true;
+            }
             let new_stmt = typecheck_stmt(
                 *stmt,
                 defined_functions,
                 &mut defined_vars,
                 should_return.clone(),
                 &mut shadowed_vars,
+                warnings,
             )?;
+            reachable = reachable && falls_through(&new_stmt);
             tc_stmts.push(new_stmt);
         }
     }
-    Ok(TCBlock { stmts: tc_stmts })
+    Ok(TCBlock {
+        stmts: tc_stmts,
+        span,
+    })
 }
 
 fn typecheck_stmt(
@@ -130,19 +750,23 @@ fn typecheck_stmt(
     defined_vars: &mut HashMap<String, TCType>, // idk what to do with the muts and the &s tbh
     should_return: Option<TCType>,
     shadowed_vars: &mut HashMap<String, TCType>,
+    warnings: &mut Vec<Diagnostic>,
 ) -> Result<TCStmt> {
-    let new_stmt = match stmt {
-        Stmt::Blk(b) => TCStmt::Blk(typecheck_block(
+    let stmt_span = stmt.span;
+    let new_stmt = match stmt.kind {
+        StmtKind::Blk(b) => TCStmt::Blk(typecheck_block(
             b,
             &defined_functions,
             defined_vars.clone(),
             should_return.clone(),
             HashMap::new(), // entering a new block allows shadowing existing vars
+            warnings,
         )?),
-        Stmt::ReturnStmt(exp) => match (exp, should_return.clone()) {
+        StmtKind::ReturnStmt(exp) => match (exp, should_return.clone()) {
             (None, None) => TCStmt::ReturnStmt(None),
             (Some(exp), Some(should_return)) => {
                 let tcexp = typecheck_exp(exp, defined_functions, &defined_vars)?;
+                let tcexp = literal_as(tcexp, should_return);
                 if tcexp.type_ != should_return {
                     Err(anyhow!("function returns incorrect type"))?
                 } else {
@@ -151,9 +775,10 @@ fn typecheck_stmt(
             }
             _ => Err(anyhow!("function returns incorrect type"))?,
         },
-        Stmt::VDeclStmt { vdecl, exp } => {
+        StmtKind::VDeclStmt { vdecl, exp } => {
             let vdecl: TCVDecl = vdecl.try_into()?;
             let exp = typecheck_exp(exp, defined_functions, &defined_vars)?;
+            let exp = literal_as(exp, vdecl.type_);
             if let TCType::Ref(_, pointer_type) = vdecl.type_ {
                 if let TCExp::VarVal(_) = exp.exp {
                     if exp.type_ != TCType::AtomType(pointer_type) {
@@ -183,10 +808,10 @@ fn typecheck_stmt(
 
             TCStmt::VDeclStmt { vdecl, exp }
         }
-        Stmt::ExpStmt(exp) => {
+        StmtKind::ExpStmt(exp) => {
             TCStmt::ExpStmt(typecheck_exp(exp, defined_functions, &defined_vars)?)
         }
-        Stmt::WhileStmt { cond, stmt } => {
+        StmtKind::WhileStmt { cond, stmt } => {
             let cond = typecheck_exp(cond, defined_functions, &defined_vars)?;
             let new_stmt = typecheck_stmt(
                 *stmt,
@@ -194,6 +819,7 @@ fn typecheck_stmt(
                 &mut defined_vars.clone(),
                 should_return.clone(),
                 &mut shadowed_vars.clone(),
+                warnings,
             )?;
 
             // check that the condition is actually a bool. unsure if this is necessary.
@@ -206,7 +832,7 @@ fn typecheck_stmt(
                 Err(anyhow!("non-boolean expression in while loop condition"))?
             }
         }
-        Stmt::IfStmt {
+        StmtKind::IfStmt {
             cond,
             stmt,
             else_stmt,
@@ -218,6 +844,7 @@ fn typecheck_stmt(
                 &mut defined_vars.clone(),
                 should_return.clone(),
                 &mut shadowed_vars.clone(),
+                warnings,
             )?;
 
             if let TCType::AtomType(TCAtomType::BoolType) = cond.type_ {
@@ -228,6 +855,7 @@ fn typecheck_stmt(
                         &mut defined_vars.clone(),
                         should_return.clone(),
                         &mut shadowed_vars.clone(),
+                        warnings,
                     )?;
                     TCStmt::IfStmt {
                         cond,
@@ -245,15 +873,63 @@ fn typecheck_stmt(
                 Err(anyhow!("non-boolean expression in while loop condition"))?
             }
         }
-        Stmt::PrintStmt(exp) => {
-            TCStmt::PrintStmt(typecheck_exp(exp, defined_functions, &defined_vars)?)
+        StmtKind::PrintStmt(exp) => {
+            let exp = typecheck_exp(exp, defined_functions, &defined_vars)?;
+            if let TCType::Array(_) = exp.type_ {
+                Err(Diagnostic::new(exp.span, "arrays can't be printed, only their elements"))?
+            }
+            TCStmt::PrintStmt(exp)
+        }
+        StmtKind::PrintStmtSlit(stri) => TCStmt::PrintStmtSlit(stri),
+        StmtKind::Assert(exp) => {
+            let cond = typecheck_exp(exp, defined_functions, &defined_vars)?;
+            if cond.type_ != TCType::AtomType(TCAtomType::BoolType) {
+                Err(Diagnostic::new(cond.span, "assert needs a bool expression"))?
+            }
+            TCStmt::Assert(cond)
         }
-        Stmt::PrintStmtSlit(stri) => TCStmt::PrintStmtSlit(stri),
+        StmtKind::AssertEq { lhs, rhs } => {
+            let lhs = typecheck_exp(lhs, defined_functions, &defined_vars)?;
+            let rhs = typecheck_exp(rhs, defined_functions, &defined_vars)?;
+            let (lhs, rhs) = literals_as_each_other(lhs, rhs);
+            if matches!(lhs.type_, TCType::VoidType | TCType::Array(_)) || lhs.type_ != rhs.type_ {
+                Err(Diagnostic::new(
+                    stmt_span,
+                    format!(
+                        "assert_eq compares values of the same type, got {:?} and {:?}",
+                        lhs.type_, rhs.type_
+                    ),
+                ))?
+            }
+            TCStmt::AssertEq { lhs, rhs }
+        }
+        // parse::parse doesn't hand over programs with syntax errors in them
+        StmtKind::Error => Err(Diagnostic::new(stmt_span, "syntax error"))?,
     };
     Ok(new_stmt)
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+// whether running stmt can finish without returning. there's no break, so a while (true)
+// loop only ever finishes by returning
+pub fn falls_through(stmt: &TCStmt) -> bool {
+    match stmt {
+        TCStmt::ReturnStmt(_) => false,
+        TCStmt::Blk(blk) => falls_through_block(blk),
+        TCStmt::IfStmt {
+            stmt,
+            else_stmt: Some(else_stmt),
+            ..
+        } => falls_through(stmt) || falls_through(else_stmt),
+        TCStmt::WhileStmt { cond, .. } => cond.exp != TCExp::Literal(Lit::LitBool(true)),
+        _ => true,
+    }
+}
+
+fn falls_through_block(blk: &TCBlock) -> bool {
+    blk.stmts.iter().all(falls_through)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TCStmt {
     Blk(TCBlock),
     ReturnStmt(Option<TypedExp>),
@@ -273,15 +949,22 @@ pub enum TCStmt {
     },
     PrintStmt(TypedExp),
     PrintStmtSlit(String),
+    Assert(TypedExp),
+    AssertEq {
+        lhs: TypedExp,
+        rhs: TypedExp,
+    },
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TypedExp {
     pub type_: TCType,
     pub exp: TCExp,
+    #[serde(skip)]
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TCExp {
     Assign {
         varid: String,
@@ -307,14 +990,99 @@ pub enum TCExp {
         exps: Vec<TypedExp>,
         expected_args: Vec<TCType>,
     },
+    NewArray {
+        type_: TCAtomType,
+        len: Box<TypedExp>,
+    },
+    Len(Box<TypedExp>),
+    Index {
+        varid: String,
+        index: Box<TypedExp>,
+    },
+    AssignIndex {
+        varid: String,
+        index: Box<TypedExp>,
+        exp: Box<TypedExp>,
+    },
 }
 
-// atom types become atom types, ref types become atoms, void types error
+// atom types become atom types, ref types become atoms, void and array types error
 pub fn maybe_deref(type_: TCType) -> Result<TCAtomType> {
     match type_ {
         TCType::AtomType(tca) => Ok(tca),
         TCType::VoidType => Err(anyhow!("cannot deref voidtype")),
         TCType::Ref(_, tca) => Ok(tca),
+        TCType::Array(_) => Err(anyhow!(
+            "arrays can only be declared, assigned, passed, returned, indexed and given to len"
+        )),
+    }
+}
+
+// literals are checked against what their context expects, as well as typed bottom-up. an
+// expression made of int literals alone, like 5 or -(2 * 3), is an int on its own, but takes
+// on the integer type of whatever it's declared as, assigned to, returned as, passed as or
+// compared with or combined with in a BinOp. anything with a variable or a call in it keeps its
+// type, so there are still no implicit casts. fold.rs checks a cint literal expression fits the
+// same way it checks any other constant cint
+fn literal_as(exp: TypedExp, expected: TCType) -> TypedExp {
+    match maybe_deref(expected) {
+        Ok(expected) if is_integer(expected) && is_int_literal_exp(&exp) => retype(exp, expected),
+        _ => exp,
+    }
+}
+
+// a BinOp's operands: an int literal expression on one side takes the type of the other side
+fn literals_as_each_other(lhs: TypedExp, rhs: TypedExp) -> (TypedExp, TypedExp) {
+    match (is_int_literal_exp(&lhs), is_int_literal_exp(&rhs)) {
+        (true, false) => (literal_as(lhs, rhs.type_), rhs),
+        (false, true) => {
+            let lhs_type = lhs.type_;
+            (lhs, literal_as(rhs, lhs_type))
+        }
+        _ => (lhs, rhs),
+    }
+}
+
+fn is_integer(type_: TCAtomType) -> bool {
+    match type_ {
+        TCAtomType::IntType | TCAtomType::CIntType => true,
+        TCAtomType::FloatType | TCAtomType::BoolType => false,
+    }
+}
+
+fn is_int_literal_exp(exp: &TypedExp) -> bool {
+    match &exp.exp {
+        TCExp::Literal(Lit::LitInt(_)) => true,
+        TCExp::UnaryOp {
+            op: UOp::SignedNeg,
+            exp,
+        } => is_int_literal_exp(exp),
+        TCExp::BinOp { op, lhs, rhs } => {
+            matches!(op, BOp::Add | BOp::Sub | BOp::Mult | BOp::Div)
+                && is_int_literal_exp(lhs)
+                && is_int_literal_exp(rhs)
+        }
+        _ => false,
+    }
+}
+
+fn retype(exp: TypedExp, type_: TCAtomType) -> TypedExp {
+    let new_exp = match exp.exp {
+        TCExp::UnaryOp { op, exp } => TCExp::UnaryOp {
+            op,
+            exp: Box::new(retype(*exp, type_)),
+        },
+        TCExp::BinOp { op, lhs, rhs } => TCExp::BinOp {
+            op,
+            lhs: Box::new(retype(*lhs, type_)),
+            rhs: Box::new(retype(*rhs, type_)),
+        },
+        other => other,
+    };
+    TypedExp {
+        type_: TCType::AtomType(type_),
+        exp: new_exp,
+        span: exp.span,
     }
 }
 
@@ -323,8 +1091,9 @@ fn typecheck_exp(
     defined_functions: &HashMap<String, (TCType, Vec<TCType>)>,
     defined_vars: &HashMap<String, TCType>,
 ) -> Result<TypedExp> {
-    match exp {
-        Exp::Assign {
+    let span = exp.span;
+    match exp.kind {
+        ExpKind::Assign {
             varid,
             exp: assignment_exp,
         } => {
@@ -332,9 +1101,12 @@ fn typecheck_exp(
             let vartype = defined_vars.get(&varid);
             match vartype {
                 Some(type_) => {
+                    let assignment_exp = literal_as(assignment_exp, *type_);
                     // it makes sense to error on void types here- you would never have a ref to one or try to assign it
                     let assignment_exp_type_clone = assignment_exp.type_.clone();
-                    if maybe_deref(type_.clone())? != maybe_deref(assignment_exp.type_.clone())? {
+                    if *type_ != assignment_exp.type_
+                        && maybe_deref(type_.clone())? != maybe_deref(assignment_exp.type_.clone())?
+                    {
                         Err(anyhow!(format!("mismatched types in assign statement, varid: {:?}, vartype: {:?}, expression: {:?}", varid, type_, &assignment_exp)))?
                     }
                     let new_exp = TCExp::Assign {
@@ -344,12 +1116,13 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_: assignment_exp_type_clone,
                         exp: new_exp,
+                        span,
                     })
                 }
                 None => Err(anyhow!("assign statement to undeclared variable"))?,
             }
         }
-        Exp::Cast {
+        ExpKind::Cast {
             type_: cast_type,
             exp: casted_exp,
         } => {
@@ -370,6 +1143,7 @@ fn typecheck_exp(
                             type_: cast_type,
                             exp: Box::new(new_exp),
                         },
+                        span,
                     }),
                     _ => Err(anyhow!("illegal type cast (num to non-num)"))?,
                 },
@@ -381,6 +1155,7 @@ fn typecheck_exp(
                                 type_: cast_type,
                                 exp: Box::new(new_exp),
                             },
+                            span,
                         })
                     } else {
                         Err(anyhow!("illegal type cast (bool to non-bool)"))?
@@ -389,9 +1164,10 @@ fn typecheck_exp(
                 _ => Err(anyhow!("illegal type cast (refs or something)"))?,
             }
         }
-        Exp::BinOp { op, lhs, rhs } => {
+        ExpKind::BinOp { op, lhs, rhs } => {
             let lhs = typecheck_exp(*lhs, defined_functions, defined_vars)?;
             let rhs = typecheck_exp(*rhs, defined_functions, defined_vars)?;
+            let (lhs, rhs) = literals_as_each_other(lhs, rhs);
 
             if maybe_deref(lhs.type_.clone())? != maybe_deref(rhs.type_.clone())? {
                 // implicit casts NOT supported, per the spec
@@ -413,6 +1189,7 @@ fn typecheck_exp(
                         Ok(TypedExp {
                             type_,
                             exp: new_exp,
+                            span,
                         })
                     }
                     _ => Err(anyhow!("arithmetic operation on non-num types"))?,
@@ -426,6 +1203,7 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_: TCType::AtomType(TCAtomType::BoolType),
                         exp: new_exp,
+                        span,
                     })
                 }
                 BOp::Gt | BOp::Lt => match lhs.type_ {
@@ -440,6 +1218,7 @@ fn typecheck_exp(
                         Ok(TypedExp {
                             type_: TCType::AtomType(TCAtomType::BoolType),
                             exp: new_exp,
+                            span,
                         })
                     }
                     _ => Err(anyhow!("comparison between non-num types"))?,
@@ -454,6 +1233,7 @@ fn typecheck_exp(
                         Ok(TypedExp {
                             type_: TCType::AtomType(TCAtomType::BoolType),
                             exp: new_exp,
+                            span,
                         })
                     } else {
                         Err(anyhow!("boolean operation on non-boolean types"))?
@@ -461,7 +1241,7 @@ fn typecheck_exp(
                 }
             }
         }
-        Exp::UnaryOp { op, exp } => {
+        ExpKind::UnaryOp { op, exp } => {
             let exp = typecheck_exp(*exp, defined_functions, defined_vars)?;
             match (op.clone(), exp.type_.clone()) {
                 (UOp::BitwiseNeg, TCType::AtomType(TCAtomType::BoolType)) => {
@@ -472,6 +1252,7 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_: TCType::AtomType(TCAtomType::BoolType),
                         exp: tcexp,
+                        span,
                     })
                 }
                 (UOp::SignedNeg, TCType::AtomType(TCAtomType::IntType))
@@ -482,18 +1263,23 @@ fn typecheck_exp(
                         op,
                         exp: Box::new(exp),
                     };
-                    Ok(TypedExp { type_, exp: tcexp })
+                    Ok(TypedExp {
+                        type_,
+                        exp: tcexp,
+                        span,
+                    })
                 }
                 _ => Err(anyhow!("illegal type in unary expression"))?,
             }
         }
-        Exp::Literal(lit) => {
+        ExpKind::Literal(lit) => {
             match lit {
                 Lit::LitBool(_) => {
                     let type_ = TCType::AtomType(TCAtomType::BoolType);
                     Ok(TypedExp {
                         type_,
                         exp: TCExp::Literal(lit),
+                        span,
                     })
                 }
                 Lit::LitInt(_) => {
@@ -502,6 +1288,7 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_,
                         exp: TCExp::Literal(lit),
+                        span,
                     })
                 }
                 Lit::LitFloat(_) => {
@@ -509,11 +1296,77 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_,
                         exp: TCExp::Literal(lit),
+                        span,
                     })
                 }
             }
         }
-        Exp::VarVal(varid) => {
+        ExpKind::NewArray { type_, len } => {
+            let type_ = TCAtomType::try_from(type_)?;
+            let len = typecheck_exp(*len, defined_functions, defined_vars)?;
+            let len = literal_as(len, TCType::AtomType(TCAtomType::IntType));
+            if !maybe_deref(len.type_).is_ok_and(is_integer) {
+                Err(Diagnostic::new(len.span, "array length has to be an int or cint"))?
+            }
+            Ok(TypedExp {
+                type_: TCType::Array(type_),
+                exp: TCExp::NewArray {
+                    type_,
+                    len: Box::new(len),
+                },
+                span,
+            })
+        }
+        ExpKind::Len(array) => {
+            let array = typecheck_exp(*array, defined_functions, defined_vars)?;
+            if let TCType::Array(_) = array.type_ {
+                Ok(TypedExp {
+                    type_: TCType::AtomType(TCAtomType::IntType),
+                    exp: TCExp::Len(Box::new(array)),
+                    span,
+                })
+            } else {
+                Err(Diagnostic::new(array.span, "len needs an array"))?
+            }
+        }
+        ExpKind::Index { varid, index } => {
+            let elem_type = array_elem_type(&varid, defined_vars, span)?;
+            let index = typecheck_index(*index, defined_functions, defined_vars)?;
+            Ok(TypedExp {
+                type_: TCType::AtomType(elem_type),
+                exp: TCExp::Index {
+                    varid,
+                    index: Box::new(index),
+                },
+                span,
+            })
+        }
+        ExpKind::AssignIndex { varid, index, exp } => {
+            let elem_type = TCType::AtomType(array_elem_type(&varid, defined_vars, span)?);
+            let index = typecheck_index(*index, defined_functions, defined_vars)?;
+            let exp = typecheck_exp(*exp, defined_functions, defined_vars)?;
+            let exp = literal_as(exp, elem_type);
+            if exp.type_ != elem_type {
+                Err(Diagnostic::new(
+                    exp.span,
+                    format!(
+                        "${} holds {:?}, but the value assigned is {:?}",
+                        varid, elem_type, exp.type_
+                    ),
+                ))?
+            }
+            Ok(TypedExp {
+                type_: elem_type,
+                exp: TCExp::AssignIndex {
+                    varid,
+                    index: Box::new(index),
+                    exp: Box::new(exp),
+                },
+                span,
+            })
+        }
+        ExpKind::Error => Err(Diagnostic::new(span, "bad literal"))?,
+        ExpKind::VarVal(varid) => {
             let vartype = defined_vars.get(&varid);
             match vartype {
                 None => Err(anyhow!("variable not defined: {}", varid))?,
@@ -523,15 +1376,17 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_: TCType::AtomType(atype.clone()),
                         exp: TCExp::VarVal(varid),
+                        span,
                     })
                 }
                 Some(atype) => Ok(TypedExp {
                     type_: atype.clone(),
                     exp: TCExp::VarVal(varid),
+                    span,
                 }),
             }
         }
-        Exp::FuncCall { globid, exps } => {
+        ExpKind::FuncCall { globid, exps } => {
             // check that function is in defined_functions
             // if it is, grab the types of each of its arguments, typecheck the corresponding exp
             // in exps, and make sure the types match
@@ -548,6 +1403,10 @@ fn typecheck_exp(
 
                     for (arg_type, exp) in arg_types.iter().zip(exps) {
                         let exp = typecheck_exp(*exp, defined_functions, defined_vars)?;
+                        let exp = match arg_type {
+                            TCType::Ref(..) => exp,
+                            _ => literal_as(exp, *arg_type),
+                        };
                         let exp_type = exp.type_.clone();
 
                         // treat ref type arguments separately
@@ -578,6 +1437,7 @@ fn typecheck_exp(
                     Ok(TypedExp {
                         type_,
                         exp: new_exp,
+                        span,
                     })
                 } else {
                     if arg_types.len() > 0 {
@@ -594,6 +1454,7 @@ fn typecheck_exp(
                         Ok(TypedExp {
                             type_,
                             exp: new_exp,
+                            span,
                         })
                     }
                 }
@@ -604,10 +1465,41 @@ fn typecheck_exp(
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+// the type of what the array variable varid holds
+fn array_elem_type(
+    varid: &String,
+    defined_vars: &HashMap<String, TCType>,
+    span: Span,
+) -> Result<TCAtomType> {
+    match defined_vars.get(varid) {
+        Some(TCType::Array(elem_type)) => Ok(*elem_type),
+        Some(_) => Err(Diagnostic::new(
+            span,
+            format!("${} isn't an array, so it can't be indexed", varid),
+        ))?,
+        None => Err(anyhow!("variable not defined: {}", varid))?,
+    }
+}
+
+fn typecheck_index(
+    index: Exp,
+    defined_functions: &HashMap<String, (TCType, Vec<TCType>)>,
+    defined_vars: &HashMap<String, TCType>,
+) -> Result<TypedExp> {
+    let index = typecheck_exp(index, defined_functions, defined_vars)?;
+    let index = literal_as(index, TCType::AtomType(TCAtomType::IntType));
+    if !maybe_deref(index.type_).is_ok_and(is_integer) {
+        Err(Diagnostic::new(index.span, "array index has to be an int or cint"))?
+    }
+    Ok(index)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TCVDecl {
     pub type_: TCType,
     pub varid: String,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Copy)]
@@ -615,6 +1507,26 @@ pub enum TCType {
     AtomType(TCAtomType),
     VoidType,
     Ref(bool, TCAtomType), // noalias, type
+    Array(TCAtomType),
+}
+
+// the way the type is written in a .ek file
+impl fmt::Display for TCType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TCType::AtomType(a) => write!(f, "{}", a),
+            TCType::VoidType => write!(f, "void"),
+            TCType::Ref(false, a) => write!(f, "ref {}", a),
+            TCType::Ref(true, a) => write!(f, "noalias ref {}", a),
+            TCType::Array(a) => write!(f, "{}[]", a),
+        }
+    }
+}
+
+// (int, float) -> bool
+pub fn signature(ret: TCType, args: &[TCType]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    format!("({}) -> {}", args.join(", "), ret)
 }
 
 impl TryFrom<VDecl> for TCVDecl {
@@ -628,6 +1540,7 @@ impl TryFrom<VDecl> for TCVDecl {
             Ok(TCVDecl {
                 type_,
                 varid: t.varid,
+                span: t.span,
             })
         }
     }
@@ -638,7 +1551,12 @@ impl TryFrom<Type> for TCType {
 
     fn try_from(t: Type) -> Result<Self, Self::Error> {
         Ok(if let Type::Ref(b, t_inner) = t {
+            if let Type::Array(_) = *t_inner {
+                Err(anyhow!("refs can't point at arrays"))?
+            }
             TCType::Ref(b, TCAtomType::try_from(*t_inner)?)
+        } else if let Type::Array(t_inner) = t {
+            TCType::Array(TCAtomType::try_from(*t_inner)?)
         } else if let Type::VoidType = t {
             TCType::VoidType
         } else {
@@ -655,6 +1573,17 @@ pub enum TCAtomType {
     BoolType,
 }
 
+impl fmt::Display for TCAtomType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TCAtomType::IntType => "int",
+            TCAtomType::CIntType => "cint",
+            TCAtomType::FloatType => "float",
+            TCAtomType::BoolType => "bool",
+        })
+    }
+}
+
 impl TryFrom<Type> for TCAtomType {
     type Error = anyhow::Error;
 
@@ -666,25 +1595,55 @@ impl TryFrom<Type> for TCAtomType {
             Type::BoolType => Ok(TCAtomType::BoolType),
             Type::VoidType => Err(anyhow!("void type can't go here :(")),
             Type::Ref(_, _) => Err(anyhow!("tried to convert ref type to atom type")),
+            Type::Array(_) => Err(anyhow!("arrays can only hold ints, cints, floats and bools")),
         }
     }
 }
 
 pub fn typecheck(prog: Prog) -> Result<TCProg> {
+    typecheck_with_warnings(prog, &mut vec![])
+}
+
+// same as typecheck, but hands back any warnings found along the way
+pub fn typecheck_with_warnings(prog: Prog, warnings: &mut Vec<Diagnostic>) -> Result<TCProg> {
+    let tcprog = typecheck_module(prog, warnings)?;
+    //    All programs must define exactly one function named “run” which returns an integer (the
+    // program exit status) and takes no arguments.
+    let run_fun_t = match tcprog.funcs.iter().find(|f| f.globid == "run") {
+        Some(f) => Some((f.type_, f.args.len())),
+        None => tcprog
+            .externs
+            .iter()
+            .find(|e| e.globid == "run")
+            .map(|e| (e.type_, e.args.len())),
+    };
+    match run_fun_t {
+        Some((TCType::AtomType(TCAtomType::IntType), 0)) => Ok(tcprog),
+        Some(_) => Err(anyhow!("run function has incorrect type")),
+        None => Err(anyhow!("no function named run")),
+    }
+}
+
+// the return and argument types of every extern and function in prog, by name.
+// every signature is known before any body is checked, so functions can call ones defined
+// further down, or each other
+pub fn function_types(prog: &Prog) -> Result<HashMap<String, (TCType, Vec<TCType>)>> {
     let mut fn_name_to_type: HashMap<String, (TCType, Vec<TCType>)> = HashMap::new();
-    let mut tcprog_externs = vec![];
-    for e in prog.externs {
-        let e_tc: TCExtern = e.try_into()?;
-        if let Some(_) =
-            fn_name_to_type.insert(e_tc.globid.clone(), (e_tc.type_.clone(), e_tc.args.clone()))
-        {
-            return Err(anyhow!("duplicate extern name: {}", e_tc.globid.clone()));
+    for e in prog.externs.iter() {
+        let args = e
+            .args
+            .clone()
+            .unwrap_or(vec![])
+            .into_iter()
+            .map(|x| x.try_into())
+            .collect::<Result<Vec<TCType>>>()?;
+        let type_ = e.type_.clone().try_into()?;
+        if let Some(_) = fn_name_to_type.insert(e.globid.clone(), (type_, args)) {
+            return Err(anyhow!("duplicate extern name: {}", e.globid.clone()));
         }
-        tcprog_externs.push(e_tc);
     }
 
-    let mut tcprog_funcs = vec![];
-    for f in prog.funcs {
+    for f in prog.funcs.iter() {
         if let Some(_) = fn_name_to_type.insert(
             f.globid.clone(),
             (
@@ -702,21 +1661,32 @@ pub fn typecheck(prog: Prog) -> Result<TCProg> {
         ) {
             return Err(anyhow!("duplicate function name: {}", f.globid.clone()));
         }
-        tcprog_funcs.push(typecheck_fn(f, &mut fn_name_to_type)?);
     }
-    //    All programs must define exactly one function named “run” which returns an integer (the
-    // program exit status) and takes no arguments.
+    Ok(fn_name_to_type)
+}
 
-    if let Some(run_fun_t) = fn_name_to_type.get("run") {
-        if run_fun_t.0 == TCType::AtomType(TCAtomType::IntType) && run_fun_t.1.len() == 0 {
-            Ok(TCProg {
-                externs: tcprog_externs,
-                funcs: tcprog_funcs,
-            })
-        } else {
-            Err(anyhow!("run function has incorrect type"))
-        }
-    } else {
-        Err(anyhow!("no function named run"))
+// typecheck a file that doesn't have to define run, like one that's only imported
+pub fn typecheck_module(prog: Prog, warnings: &mut Vec<Diagnostic>) -> Result<TCProg> {
+    let fn_name_to_type = function_types(&prog)?;
+    let tcprog_externs = prog
+        .externs
+        .into_iter()
+        .map(TCExtern::try_from)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut tcprog_globals = vec![];
+    for g in prog.globals {
+        let tcglobal = typecheck_global(g, &fn_name_to_type, &tcprog_globals, warnings)?;
+        tcprog_globals.push(tcglobal);
     }
+
+    let mut tcprog_funcs = vec![];
+    for f in prog.funcs {
+        tcprog_funcs.push(typecheck_fn(f, &fn_name_to_type, &tcprog_globals, warnings)?);
+    }
+    Ok(TCProg {
+        externs: tcprog_externs,
+        globals: tcprog_globals,
+        funcs: tcprog_funcs,
+    })
 }